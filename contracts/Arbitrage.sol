@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.19;
+
+// Executes a detected opportunity atomically: swaps the contract's own `path[0]` balance along
+// `path`, one hop per UniswapV2-compatible router, and reverts unless it ends with at least
+// `minProfit` more than it started with. Only the owner (the bot's signer) can trade or withdraw.
+
+interface IERC20 {
+    function balanceOf(address account) external view returns (uint256);
+    function approve(address spender, uint256 amount) external returns (bool);
+    function transfer(address to, uint256 amount) external returns (bool);
+}
+
+interface IUniswapV2Router {
+    function swapExactTokensForTokens(
+        uint256 amountIn,
+        uint256 amountOutMin,
+        address[] calldata path,
+        address to,
+        uint256 deadline
+    ) external returns (uint256[] memory amounts);
+}
+
+contract Arbitrage {
+    address public immutable owner;
+
+    event Executed(address indexed token, uint256 amountIn, uint256 profit);
+
+    constructor() {
+        owner = msg.sender;
+    }
+
+    modifier onlyOwner() {
+        require(msg.sender == owner, "Arbitrage: NOT_OWNER");
+        _;
+    }
+
+    /// Swap `amountIn` of `path[0]` through `path`, hop `i` on `routers[i]`; `path` must end where it starts
+    function execute(address[] calldata routers, address[] calldata path, uint256 amountIn, uint256 minProfit)
+        external
+        onlyOwner
+        returns (uint256 profit)
+    {
+        require(routers.length >= 1 && path.length == routers.length + 1, "Arbitrage: INVALID_PATH");
+        require(path[0] == path[path.length - 1], "Arbitrage: OPEN_PATH");
+        IERC20 token = IERC20(path[0]);
+        uint256 balanceBefore = token.balanceOf(address(this));
+
+        uint256 amount = amountIn;
+        address[] memory hop = new address[](2);
+        for (uint256 i; i < routers.length; i++) {
+            (hop[0], hop[1]) = (path[i], path[i + 1]);
+            _call(hop[0], abi.encodeCall(IERC20.approve, (routers[i], amount)));
+            uint256[] memory amounts =
+                IUniswapV2Router(routers[i]).swapExactTokensForTokens(amount, 0, hop, address(this), block.timestamp);
+            amount = amounts[1];
+        }
+
+        uint256 balanceAfter = token.balanceOf(address(this));
+        require(balanceAfter >= balanceBefore + minProfit, "Arbitrage: INSUFFICIENT_PROFIT");
+        profit = balanceAfter - balanceBefore;
+        emit Executed(path[0], amountIn, profit);
+    }
+
+    function withdraw(address token, uint256 amount) external onlyOwner {
+        _call(token, abi.encodeCall(IERC20.transfer, (owner, amount)));
+    }
+
+    /// Call `token` and require success: either no return data (tokens like USDT) or `true`
+    function _call(address token, bytes memory data) private {
+        (bool success, bytes memory returned) = token.call(data);
+        require(
+            success && (returned.length == 0 || abi.decode(returned, (bool))) && token.code.length > 0,
+            "Arbitrage: TOKEN_CALL_FAILED"
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use ethers::abi::{Detokenize, Tokenize};
+use ethers::prelude::*;
+use ethers::utils::{Anvil, AnvilInstance};
+use ethers_solc::artifacts::CompilerOutput;
+use ethers_solc::Solc;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, ensure, Result};
+use crate::arbitrage::{Evaluation, Opportunity};
+use crate::blocks::{Head, Heads};
+use crate::config::Config;
+use crate::errors::ErrorStats;
+use crate::mock::Collected;
+use crate::rpc;
+use crate::sinks::Sink;
+use crate::token::TokenRegistry;
+
+/// Solidity sources deployed by the tests; compiled with `solc` from PATH (or `SOLC_PATH`)
+const CONTRACTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/contracts");
+
+pub type Deployer = SignerMiddleware<Provider<Http>, LocalWallet>;
+
+/// A fresh anvil chain with the contracts under `contracts/` compiled, deploying from its first
+/// funded account.
+pub struct Chain {
+    pub anvil: AnvilInstance,
+    pub client: Arc<Deployer>,
+    contracts: CompilerOutput,
+}
+
+impl Chain {
+    pub async fn start() -> Result<Chain> {
+        let contracts = Solc::default().compile_source(CONTRACTS)?;
+        ensure!(!contracts.has_error(), "contracts do not compile: {:?}", contracts.errors);
+        let anvil = Anvil::new().spawn();
+        let provider = Provider::<Http>::try_from(anvil.endpoint())?.interval(Duration::from_millis(10));
+        let wallet = LocalWallet::from(anvil.keys()[0].clone()).with_chain_id(anvil.chain_id());
+        Ok(Chain { client: Arc::new(SignerMiddleware::new(provider, wallet)), anvil, contracts })
+    }
+
+    pub async fn deploy<T: Tokenize>(&self, name: &str, args: T) -> Result<Contract<Deployer>> {
+        let contract = self.contracts.find(name).ok_or_else(|| anyhow!("no contract {name} under {CONTRACTS}"))?;
+        let (abi, bytecode, _) = contract.into_parts_or_default();
+        Ok(ContractFactory::new(abi, bytecode, self.client.clone()).deploy(args)?.send().await?)
+    }
+
+    /// Mintable ERC-20
+    pub async fn token(&self, symbol: &str, decimals: u8) -> Result<Contract<Deployer>> {
+        self.deploy("TestToken", (symbol.to_string(), decimals)).await
+    }
+
+    /// UniswapV2 factory and router; returns the router
+    pub async fn v2(&self) -> Result<Contract<Deployer>> {
+        let factory = self.deploy("UniswapV2Factory", ()).await?;
+        self.deploy("UniswapV2Router", factory.address()).await
+    }
+
+    /// Create the `token_a`/`token_b` pool behind `router` holding `reserve_a` and `reserve_b` base units
+    pub async fn pool(
+        &self,
+        router: &Contract<Deployer>,
+        (token_a, reserve_a): (&Contract<Deployer>, U256),
+        (token_b, reserve_b): (&Contract<Deployer>, U256),
+    ) -> Result<Address> {
+        let factory = self.at("UniswapV2Factory", router.method("factory", ())?.call().await?)?;
+        send(factory.method::<_, Address>("createPair", (token_a.address(), token_b.address()))?).await?;
+        let pair: Address = factory.method("getPair", (token_a.address(), token_b.address()))?.call().await?;
+        send(token_a.method::<_, ()>("mint", (pair, reserve_a))?).await?;
+        send(token_b.method::<_, ()>("mint", (pair, reserve_b))?).await?;
+        send(self.at("UniswapV2Pair", pair)?.method::<_, ()>("sync", ())?).await?;
+        Ok(pair)
+    }
+
+    /// The deployed contract `name` at `address`
+    pub fn at(&self, name: &str, address: Address) -> Result<Contract<Deployer>> {
+        let contract = self.contracts.find(name).ok_or_else(|| anyhow!("no contract {name} under {CONTRACTS}"))?;
+        let (abi, _, _) = contract.into_parts_or_default();
+        Ok(Contract::new(address, abi, self.client.clone()))
+    }
+
+    /// Config from `vars` alone (no process environment or `.env`), pointed at this chain, without
+    /// cycles, fixtures or a WebSocket
+    pub fn config(&self, vars: &[(&str, String)]) -> Config {
+        let mut values = vec![
+            ("POLYGON_RPC", self.anvil.endpoint()),
+            ("POLYGON_WS", String::new()),
+            ("ARCHIVE_RPC", String::new()),
+            ("CYCLES", String::new()),
+            ("DISCOVERY", "routes".to_string()),
+            ("OPTIMIZE_SIZE", "false".to_string()),
+            ("SYNC_ONLY", "false".to_string()),
+            ("RECORD_FIXTURE", String::new()),
+            ("REPLAY_FIXTURE", String::new()),
+            ("SLIPPAGE_BPS", "30".to_string()),
+            ("EXECUTE", "false".to_string()),
+        ];
+        values.extend_from_slice(vars);
+        Config::from_values(&values)
+    }
+}
+
+/// Send a transaction and wait for it to be mined
+pub async fn send<D: Detokenize>(call: ContractCall<Deployer, D>) -> Result<TransactionReceipt> {
+    let receipt = call.send().await?.await?.ok_or_else(|| anyhow!("transaction dropped"))?;
+    ensure!(receipt.status == Some(1.into()), "transaction {:?} reverted", receipt.transaction_hash);
+    Ok(receipt)
+}
+
+/// The bot's provider for `cfg`
+pub fn provider(cfg: &Config) -> Result<Arc<rpc::Client>> {
+    Ok(Arc::new(rpc::connect(&cfg.rpc_urls, cfg.max_concurrency, cfg.rpc_quorum, None)?))
+}
+
+/// Run the bot's evaluation once, at the chain's latest block, the way the live loop does.
+pub async fn evaluate(cfg: &Config) -> Result<(Head, Vec<Opportunity>, Vec<Evaluation>)> {
+    let provider = provider(cfg)?;
+    let venues = crate::connect_venues(cfg, &provider, &ErrorStats::new());
+    let tokens = TokenRegistry::new(provider.clone());
+    let head = Heads::poll(provider.clone(), Duration::ZERO).next().await?;
+    let gas = crate::gas_costs(cfg, &provider, &venues, &tokens, head).await;
+
+    let collected = Collected::default();
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(collected.clone())];
+    crate::evaluate(cfg, &venues, &tokens, &gas, head, &mut sinks).await?;
+    let opportunities = collected.opportunities.lock().unwrap().clone();
+    let evaluations = collected.evaluations.lock().unwrap().clone();
+    Ok((head, opportunities, evaluations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::get_amount_out;
+    use crate::executor::{Executor, Status};
+    use crate::mock::units;
+    use crate::token::from_base_units;
+    use rust_decimal::prelude::*;
+
+    const GAS_FALLBACK: f64 = 1000.0;
+    /// USDC the arbitrage contract starts with
+    const CAPITAL: u64 = 10_000;
+
+    /// Two V2 deployments whose WETH/USDC pools hold 1000 WETH priced at `cheap` and `dear` USDC.
+    struct Market {
+        chain: Chain,
+        weth: Contract<Deployer>,
+        usdc: Contract<Deployer>,
+        cheap: Contract<Deployer>,
+        dear: Contract<Deployer>,
+        multicall: Contract<Deployer>,
+        /// Pairs behind `cheap` and `dear`
+        pools: (Address, Address),
+        prices: (u64, u64),
+    }
+
+    impl Market {
+        async fn open(cheap_price: u64, dear_price: u64) -> Result<Market> {
+            let chain = Chain::start().await?;
+            let weth = chain.token("WETH", 18).await?;
+            let usdc = chain.token("USDC", 6).await?;
+            let (cheap, dear) = (chain.v2().await?, chain.v2().await?);
+            let pool = |router, price| chain.pool(router, (&weth, units(1000, 18)), (&usdc, units(1000 * price, 6)));
+            let pools = (pool(&cheap, cheap_price).await?, pool(&dear, dear_price).await?);
+            let multicall = chain.deploy("Multicall3", ()).await?;
+            Ok(Market { chain, weth, usdc, cheap, dear, multicall, pools, prices: (cheap_price, dear_price) })
+        }
+
+        fn config(&self, multicall: bool, local_amm: bool) -> Config {
+            self.chain.config(&self.vars(multicall, local_amm))
+        }
+
+        fn vars(&self, multicall: bool, local_amm: bool) -> Vec<(&'static str, String)> {
+            vec![
+                ("VENUES", format!("Cheap:v2:{:?},Dear:v2:{:?}", self.cheap.address(), self.dear.address())),
+                ("TOKENS", format!("WETH:{:?},USDC:{:?}", self.weth.address(), self.usdc.address())),
+                ("PAIRS", "WETH/USDC:1000:5".to_string()),
+                // Gas is priced by quoting WETH on the first venue
+                ("WMATIC", format!("{:?}", self.weth.address())),
+                ("GAS_PRICE_VENUE", String::new()),
+                ("GAS_FALLBACK", GAS_FALLBACK.to_string()),
+                ("MULTICALL", multicall.to_string()),
+                ("MULTICALL3", format!("{:?}", self.multicall.address())),
+                ("LOCAL_AMM", local_amm.to_string()),
+            ]
+        }
+
+        /// Deploy the arbitrage contract, fund it with `CAPITAL` USDC and enable execution through it,
+        /// signed by the deployer. The slippage tolerance is wide enough for a second trade on the
+        /// same pools to clear the contract's min-profit check.
+        async fn executing(&self) -> Result<(Config, Contract<Deployer>)> {
+            let arbitrage = self.chain.deploy("Arbitrage", ()).await?;
+            send(self.usdc.method::<_, ()>("mint", (arbitrage.address(), units(CAPITAL, 6)))?).await?;
+            let mut vars = self.vars(false, false);
+            vars.extend([
+                ("EXECUTE", "true".to_string()),
+                ("ARB_CONTRACT", format!("{:?}", arbitrage.address())),
+                ("PRIVATE_KEY", ethers::utils::hex::encode(self.chain.anvil.keys()[0].to_bytes())),
+                ("EXECUTIONS_LOG", String::new()),
+                ("SLIPPAGE_BPS", "100".to_string()),
+            ]);
+            Ok((self.chain.config(&vars), arbitrage))
+        }
+
+        async fn executor(&self, cfg: &Config) -> Result<Executor> {
+            let provider = provider(cfg)?;
+            let tokens = TokenRegistry::new(provider.clone());
+            Executor::connect(cfg, cfg.execution.as_ref().unwrap(), provider, &tokens).await
+        }
+
+        async fn balance(&self, token: &Contract<Deployer>, owner: Address) -> Result<U256> {
+            Ok(token.method("balanceOf", owner)?.call().await?)
+        }
+
+        async fn nonce(&self) -> Result<U256> {
+            Ok(self.chain.client.get_transaction_count(self.chain.client.address(), None).await?)
+        }
+
+        /// Net profit of 1000 USDC bought into WETH on `Cheap` and sold on `Dear`, by the router
+        /// formula, after `gas_cost`
+        fn expected_profit(&self, gas_cost: f64) -> f64 {
+            let (cheap, dear) = self.prices;
+            let weth = get_amount_out(units(1000, 6), units(1000 * cheap, 6), units(1000, 18), 3000).unwrap();
+            let usdc = get_amount_out(weth, units(1000, 18), units(1000 * dear, 6), 3000).unwrap();
+            (from_base_units(usdc, 6).unwrap() - Decimal::from(1000)).to_f64().unwrap() - gas_cost
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and solc on PATH"]
+    async fn detects_skewed_pools_through_the_router() {
+        let market = Market::open(2000, 2100).await.unwrap();
+        let (head, opportunities, evaluations) = evaluate(&market.config(false, false)).await.unwrap();
+
+        assert_eq!(opportunities.len(), 1, "{opportunities:?}");
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.direction, "Cheap->Dear");
+        assert_eq!(opportunity.path, vec!["USDC", "WETH", "USDC"]);
+        assert_eq!(opportunity.block_number, head.number.as_u64());
+        // Gas was priced from the chain, not the fallback
+        assert!(opportunity.gas_cost > 0.0 && opportunity.gas_cost < GAS_FALLBACK);
+        assert!((opportunity.net_profit - market.expected_profit(opportunity.gas_cost)).abs() < 1e-6);
+        assert!(opportunity.net_profit > 40.0);
+
+        // Both directions were evaluated, the reverse one at a loss
+        assert_eq!(evaluations.len(), 1);
+        let directions: Vec<&str> = evaluations[0].quotes.iter().map(|quote| quote.direction.as_str()).collect();
+        assert_eq!(directions, vec!["Cheap->Dear", "Dear->Cheap"]);
+        assert!(evaluations[0].quotes[1].net_profit < 0.0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and solc on PATH"]
+    async fn multicall_and_local_reserves_find_the_same_opportunity() {
+        let market = Market::open(2000, 2100).await.unwrap();
+        let (_, expected, _) = evaluate(&market.config(false, false)).await.unwrap();
+        for (multicall, local_amm) in [(true, false), (false, true), (true, true)] {
+            let (_, found, _) = evaluate(&market.config(multicall, local_amm)).await.unwrap();
+            assert_eq!(found.len(), 1, "multicall={multicall} local_amm={local_amm}");
+            assert_eq!(found[0].direction, expected[0].direction);
+            assert_eq!(found[0].amount_out, expected[0].amount_out, "multicall={multicall} local_amm={local_amm}");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and solc on PATH"]
+    async fn balanced_pools_are_evaluated_but_not_recorded() {
+        let market = Market::open(2000, 2000).await.unwrap();
+        let (_, opportunities, evaluations) = evaluate(&market.config(true, false)).await.unwrap();
+
+        assert!(opportunities.is_empty(), "{opportunities:?}");
+        assert_eq!(evaluations.len(), 1);
+        assert_eq!(evaluations[0].quotes.len(), 2);
+        assert!(!evaluations[0].profitable());
+        // Two fees and no spread: roughly 0.6% under water before gas
+        assert!(evaluations[0].spread_pct().unwrap() < -0.5);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and solc on PATH"]
+    async fn executes_the_detected_opportunity_atomically() {
+        let market = Market::open(2000, 2100).await.unwrap();
+        let (cfg, arbitrage) = market.executing().await.unwrap();
+        let (_, opportunities, _) = evaluate(&cfg).await.unwrap();
+        assert_eq!(opportunities.len(), 1, "{opportunities:?}");
+        let opportunity = &opportunities[0];
+        assert!(opportunity.robust);
+
+        let nonce = market.nonce().await.unwrap();
+        let execution = market.executor(&cfg).await.unwrap().execute(opportunity).await;
+        assert_eq!(execution.status, Status::Included, "{:?}", execution.error);
+        assert_eq!(execution.nonce, Some(nonce.as_u64()));
+        assert!(execution.tx_hash.is_some());
+        assert!(execution.gas_used.unwrap() > 0 && execution.gas_cost.unwrap() > 0.0);
+
+        // Nothing moved between detection and execution, so the contract gained exactly the quoted gross profit
+        let realised = execution.realised_profit.unwrap();
+        assert!((realised - opportunity.gross_profit).abs() < 1e-6, "{realised} != {}", opportunity.gross_profit);
+        assert!(realised >= execution.min_profit.unwrap());
+        let gained = market.balance(&market.usdc, arbitrage.address()).await.unwrap() - units(CAPITAL, 6);
+        assert_eq!(from_base_units(gained, 6).unwrap().to_f64().unwrap(), realised);
+        assert_eq!(market.balance(&market.weth, arbitrage.address()).await.unwrap(), U256::zero());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and solc on PATH"]
+    async fn consecutive_executions_use_consecutive_nonces() {
+        let market = Market::open(2000, 2100).await.unwrap();
+        let (cfg, _) = market.executing().await.unwrap();
+        let (_, opportunities, _) = evaluate(&cfg).await.unwrap();
+        let executor = market.executor(&cfg).await.unwrap();
+
+        let nonce = market.nonce().await.unwrap().as_u64();
+        let first = executor.execute(&opportunities[0]).await;
+        let second = executor.execute(&opportunities[0]).await;
+        assert_eq!((first.status, second.status), (Status::Included, Status::Included), "{:?}", second.error);
+        assert_eq!((first.nonce, second.nonce), (Some(nonce), Some(nonce + 1)));
+        assert_ne!(first.tx_hash, second.tx_hash);
+        // The first trade narrowed the spread
+        assert!(second.realised_profit.unwrap() < first.realised_profit.unwrap());
+        assert_eq!(market.nonce().await.unwrap().as_u64(), nonce + 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs anvil and solc on PATH"]
+    async fn stale_opportunities_are_rejected_without_spending_gas() {
+        let market = Market::open(2000, 2100).await.unwrap();
+        let (cfg, arbitrage) = market.executing().await.unwrap();
+        let (_, opportunities, _) = evaluate(&cfg).await.unwrap();
+
+        // Someone else closes the spread: 50 WETH more in the dear pool prices it at 2000 too
+        send(market.weth.method::<_, ()>("mint", (market.pools.1, units(50, 18))).unwrap()).await.unwrap();
+        send(market.chain.at("UniswapV2Pair", market.pools.1).unwrap().method::<_, ()>("sync", ()).unwrap()).await.unwrap();
+
+        let nonce = market.nonce().await.unwrap();
+        let execution = market.executor(&cfg).await.unwrap().execute(&opportunities[0]).await;
+        assert_eq!(execution.status, Status::Rejected);
+        assert!(execution.error.as_deref().unwrap().contains("INSUFFICIENT_PROFIT"), "{:?}", execution.error);
+        assert_eq!((execution.tx_hash, execution.nonce), (None, None));
+        assert_eq!(market.nonce().await.unwrap(), nonce);
+        assert_eq!(market.balance(&market.usdc, arbitrage.address()).await.unwrap(), units(CAPITAL, 6));
+    }
+}
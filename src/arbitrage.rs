@@ -1,103 +1,744 @@
-use ethers::prelude::*;
-use ethers::providers::Provider;
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
-use anyhow::Result;
+use std::time::SystemTime;
+use anyhow::{anyhow, Result};
+use ethers::types::{Address, BlockId, U256};
+use futures::future::join_all;
 use rust_decimal::Decimal;
-use rust_decimal::prelude::ToPrimitive;
-use crate::dex;
-
-/// Convert human USDC (like 1000.23) -> U256 (USDC has 6 decimals)
-pub fn usdc_to_wei(amount_usdc: Decimal) -> U256 {
-    let scaled = amount_usdc * Decimal::from_i128_with_scale(1_000_000, 0); // * 1e6
-    // Round down to avoid accidental overspend
-    let as_i128 = scaled.trunc().to_i128().expect("overflow converting to i128");
-    // U256 from u128
-    let as_u128 = as_i128 as u128;
-    U256::from(as_u128)
-}
-
-/// Convert U256 (6 decimals) -> Decimal human USDC
-pub fn u256_to_usdc(value: U256) -> Decimal {
-    let as_u128 = value.as_u128();
-    // Use from_i128_with_scale instead of from_u128
-    Decimal::from_i128_with_scale(as_u128 as i128, 0) / Decimal::from_i128_with_scale(1_000_000, 0)
-}
-
-/// Run simulation:
-/// - Buy WETH on DEX A using `trade_amount_usdc` (path = [USDC, WETH]) -> get weth_received
-/// - Sell that weth on DEX B (path = [WETH, USDC]) -> get usdc_received
-/// Profit = usdc_received - trade_amount_usdc - gas_usdc
-pub async fn simulate_trade_usdc(
-    provider: Arc<Provider<ethers::providers::Http>>,
-    dex_buy_router: Address,
-    dex_sell_router: Address,
-    usdc_addr: Address,
-    weth_addr: Address,
-    trade_amount_usdc: Decimal,
-    gas_usdc: Decimal,
-) -> Result<(String, Decimal)> {
-    // amount_in for buy: trade_amount_usdc in USDC base units
-    let amount_in_buy = usdc_to_wei(trade_amount_usdc);
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use crate::blocks::Head;
+use crate::config::PairConfig;
+use crate::dex::{self, Dex};
+use crate::gas::GasCosts;
+use crate::token::{TokenInfo, TokenRegistry};
+use crate::triangular::{self, CycleResult, Leg};
+
+/// Golden-section iterations when no closed form is available; each costs two quotes
+const SIZING_ITERATIONS: usize = 20;
+/// Points sampled for the reported profit curve
+const CURVE_POINTS: usize = 8;
 
-    // path [USDC, WETH]
-    let path_buy = vec![usdc_addr, weth_addr];
-    let amounts_buy = dex::get_amounts_out(provider.clone(), dex_buy_router, amount_in_buy, path_buy).await?;
-    // amounts_buy[0] == amount_in_buy, amounts_buy[1] == weth_received (in wei)
+/// One buy/sell combination for a pair: buy the base token with the quote token on `buy_venue`,
+/// sell it back on `sell_venue`.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub pair: String,
+    pub buy_venue: String,
+    pub sell_venue: String,
+    /// Base token bought on `buy_venue`
+    pub base_symbol: String,
+    /// Symbol of the token `profit` is denominated in
+    pub quote_symbol: String,
+    /// Profit at the pair's fixed `trade_amount`
+    pub profit: Decimal,
+    /// Profit-maximising size, when the size search ran for this route
+    pub sizing: Option<Sizing>,
+    /// Legs re-quoted at the optimal size, when that size beat the fixed one
+    pub optimal_legs: Option<Vec<Leg>>,
+    /// Gas cost in the quote token already subtracted from `profit`
+    pub gas_cost: Decimal,
+    /// Buy and sell legs at the fixed `trade_amount`, with mid prices and price impact
+    pub legs: Vec<Leg>,
+    /// Block every quote of this route (and its sizing) was pinned to
+    pub block: Head,
+}
 
-    let weth_received = amounts_buy[1];
+impl Route {
+    /// Direction label used in logs, the CSV and the dashboard, e.g. `QuickSwap->SushiSwap`
+    pub fn direction(&self) -> String {
+        format!("{}->{}", self.buy_venue, self.sell_venue)
+    }
 
-    // Now sell weth_received on DEX sell: path [WETH, USDC]
-    let path_sell = vec![weth_addr, usdc_addr];
-    let amounts_sell = dex::get_amounts_out(provider.clone(), dex_sell_router, weth_received, path_sell).await?;
-    let usdc_received = amounts_sell[1];
+    /// Profit of `best_legs`: at the optimal size if it was searched and paid more, otherwise at the fixed size
+    pub fn best_profit(&self) -> Decimal {
+        self.optimal_profit().map_or(self.profit, |optimal| optimal.max(self.profit))
+    }
 
-    let usdc_received_dec = u256_to_usdc(usdc_received);
-    let profit = usdc_received_dec - trade_amount_usdc - gas_usdc;
+    /// The legs opportunities and evaluations are recorded from
+    pub fn best_legs(&self) -> &[Leg] {
+        match (&self.optimal_legs, self.optimal_profit()) {
+            (Some(legs), Some(optimal)) if optimal > self.profit => legs,
+            _ => &self.legs,
+        }
+    }
 
-    Ok(("BUY_ON_A_SELL_B".to_string(), profit))
+    fn optimal_profit(&self) -> Option<Decimal> {
+        let legs = self.optimal_legs.as_ref()?;
+        Some(legs.last()?.amount_out - legs.first()?.amount_in - self.gas_cost)
+    }
 }
 
-/// Helper that tries both directions and returns best (A->B or B->A)
-/// Returns Ok(None) if both directions fail, Ok(Some((direction, profit))) if at least one works
-pub async fn best_direction(
-    provider: Arc<Provider<ethers::providers::Http>>,
-    dex_a: Address,
-    dex_b: Address,
-    usdc_addr: Address,
-    weth_addr: Address,
-    trade_amount_usdc: Decimal,
-    gas_usdc: Decimal,
-) -> Result<Option<(String, Decimal)>> {
-    // A buy -> B sell
-    let res_ab = simulate_trade_usdc(provider.clone(), dex_a, dex_b, usdc_addr, weth_addr, trade_amount_usdc, gas_usdc).await;
-    // B buy -> A sell
-    let res_ba = simulate_trade_usdc(provider.clone(), dex_b, dex_a, usdc_addr, weth_addr, trade_amount_usdc, gas_usdc).await;
-
-    let profit_ab = match res_ab {
-        Ok((dir, p)) => Some((dir, p)),
-        Err(e) => {
-            eprintln!("A->B simulation failed: {:?}", e);
-            None
+/// A detected opportunity in the shape every sink (CSV, dashboard, ...) records. Amounts are in
+/// human units of the tokens along `path`; profits and gas in `quote_symbol`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Opportunity {
+    /// `{block}:{pair}:{direction}`, unique per evaluated block
+    pub id: String,
+    /// UNIX seconds when it was priced
+    pub timestamp: u64,
+    pub block_number: u64,
+    pub block_hash: String,
+    /// Pair or cycle label, e.g. `WETH/USDC` or `USDC>WETH>WMATIC>USDC`
+    pub pair: String,
+    /// Venue of the first leg
+    pub buy_venue: String,
+    /// Venue of the last leg
+    pub sell_venue: String,
+    /// Venues in order, e.g. `QuickSwap->SushiSwap`, or `QuickSwap>QuickSwap>SushiSwap` for cycles
+    pub direction: String,
+    /// Token symbols visited, first == last
+    pub path: Vec<String>,
+    pub amount_in: f64,
+    /// Output of the first leg, in `path[1]`
+    pub intermediate_amount: f64,
+    pub amount_out: f64,
+    pub quote_symbol: String,
+    /// amount_out − amount_in
+    pub gross_profit: f64,
+    pub gas_cost: f64,
+    /// gross_profit − gas_cost
+    pub net_profit: f64,
+    /// Input and net profit at the optimal size, when the size search ran and beat the fixed size;
+    /// the legs, amounts and profits above are then priced at this size too
+    pub optimal_amount: Option<f64>,
+    pub optimal_profit: Option<f64>,
+    /// Largest price impact of any leg, in percent
+    pub price_impact: f64,
+    /// Net profit with the final output at its minimum-out under the slippage tolerance
+    pub slippage_profit: f64,
+    /// Whether `slippage_profit` still clears the minimum profit
+    pub robust: bool,
+    /// Milliseconds from the block being picked up to the opportunity being priced
+    pub latency_ms: u64,
+    /// The venue quote behind each leg
+    pub legs: Vec<LegQuote>,
+    /// Input and final output exactly as priced, in human units of `quote_symbol`, for execution;
+    /// `None` when loaded from the store
+    #[serde(skip)]
+    pub exact: Option<(Decimal, Decimal)>,
+}
+
+/// One leg of a recorded opportunity: the quote it was priced from.
+#[derive(Debug, Clone, Serialize)]
+pub struct LegQuote {
+    pub venue: String,
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: f64,
+    pub amount_out: f64,
+    pub mid_price: f64,
+    /// In percent
+    pub price_impact: f64,
+}
+
+impl Opportunity {
+    /// Opportunity for a pair route, at the optimal size when it beats the fixed size.
+    pub fn from_route(route: &Route, slippage_bps: u32, min_profit: Decimal) -> Result<Opportunity> {
+        let legs = route.best_legs();
+        let mut opportunity =
+            Opportunity::from_legs(&route.pair, route.direction(), legs, route.gas_cost, route.block, slippage_bps, min_profit)?;
+        if !std::ptr::eq(legs, route.legs.as_slice()) {
+            opportunity.optimal_amount = Some(opportunity.amount_in);
+            opportunity.optimal_profit = Some(opportunity.net_profit);
+        }
+        Ok(opportunity)
+    }
+
+    /// Opportunity for a priced cycle.
+    pub fn from_cycle(result: &CycleResult, slippage_bps: u32, min_profit: Decimal) -> Result<Opportunity> {
+        Opportunity::from_legs(&result.cycle, result.route(), &result.legs, result.gas_cost, result.block, slippage_bps, min_profit)
+    }
+
+    fn from_legs(
+        pair: &str,
+        direction: String,
+        legs: &[Leg],
+        gas_cost: Decimal,
+        block: Head,
+        slippage_bps: u32,
+        min_profit: Decimal,
+    ) -> Result<Opportunity> {
+        let (first, last) = match (legs.first(), legs.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(anyhow!("{pair} has no legs")),
+        };
+        let f = |value: Decimal| value.to_f64().unwrap_or(0.0);
+        let gross_profit = last.amount_out - first.amount_in;
+        let slippage_profit = triangular::profit_at_slippage(legs, gas_cost, slippage_bps);
+        let price_impact = legs.iter().map(|leg| leg.price_impact).max().unwrap_or_default() * Decimal::from(100);
+
+        let mut path = vec![first.token_in.clone()];
+        path.extend(legs.iter().map(|leg| leg.token_out.clone()));
+        Ok(Opportunity {
+            id: format!("{}:{}:{}", block.number, pair, direction),
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+            block_number: block.number.as_u64(),
+            block_hash: format!("{:?}", block.hash),
+            pair: pair.to_string(),
+            buy_venue: first.venue.clone(),
+            sell_venue: last.venue.clone(),
+            direction,
+            path,
+            amount_in: f(first.amount_in),
+            intermediate_amount: f(first.amount_out),
+            amount_out: f(last.amount_out),
+            quote_symbol: last.token_out.clone(),
+            gross_profit: f(gross_profit),
+            gas_cost: f(gas_cost),
+            net_profit: f(gross_profit - gas_cost),
+            optimal_amount: None,
+            optimal_profit: None,
+            price_impact: f(price_impact),
+            slippage_profit: f(slippage_profit),
+            robust: slippage_profit >= min_profit,
+            latency_ms: block.seen.elapsed().as_millis() as u64,
+            legs: legs
+                .iter()
+                .map(|leg| LegQuote {
+                    venue: leg.venue.clone(),
+                    token_in: leg.token_in.clone(),
+                    token_out: leg.token_out.clone(),
+                    amount_in: f(leg.amount_in),
+                    amount_out: f(leg.amount_out),
+                    mid_price: f(leg.mid_price),
+                    price_impact: f(leg.price_impact * Decimal::from(100)),
+                })
+                .collect(),
+            exact: Some((first.amount_in, last.amount_out)),
+        })
+    }
+}
+
+/// Outcome of evaluating one pair or cycle at one block, whether or not it cleared its threshold.
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    /// UNIX seconds when it was priced
+    pub timestamp: u64,
+    pub block_number: u64,
+    /// Pair or cycle label
+    pub pair: String,
+    pub min_profit: f64,
+    /// Best route first; empty when every venue failed
+    pub quotes: Vec<RouteQuote>,
+}
+
+/// One priced route of an evaluation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteQuote {
+    pub direction: String,
+    pub quote_symbol: String,
+    pub amount_in: f64,
+    pub amount_out: f64,
+    /// At the optimal size when one was searched
+    pub net_profit: f64,
+}
+
+impl Evaluation {
+    /// Evaluation of a pair from its ranked routes.
+    pub fn from_routes(pair: &str, routes: &[Route], min_profit: Decimal, head: Head) -> Result<Evaluation> {
+        let quotes = routes.iter().map(|route| RouteQuote::new(route.direction(), route.best_legs(), route.best_profit())).collect();
+        Evaluation::new(pair, quotes, min_profit, head)
+    }
+
+    /// Evaluation of a cycle from its priced variants.
+    pub fn from_cycles(name: &str, priced: &[CycleResult], min_profit: Decimal, head: Head) -> Result<Evaluation> {
+        let quotes = priced.iter().map(|result| RouteQuote::new(result.route(), &result.legs, result.profit)).collect();
+        Evaluation::new(name, quotes, min_profit, head)
+    }
+
+    fn new(pair: &str, quotes: Vec<RouteQuote>, min_profit: Decimal, head: Head) -> Result<Evaluation> {
+        Ok(Evaluation {
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+            block_number: head.number.as_u64(),
+            pair: pair.to_string(),
+            min_profit: min_profit.to_f64().unwrap_or(0.0),
+            quotes,
+        })
+    }
+
+    pub fn best(&self) -> Option<&RouteQuote> {
+        self.quotes.first()
+    }
+
+    /// Gross spread of the best route in percent: how much more came out than went in, before gas
+    pub fn spread_pct(&self) -> Option<f64> {
+        self.best().filter(|best| best.amount_in > 0.0).map(|best| (best.amount_out / best.amount_in - 1.0) * 100.0)
+    }
+
+    pub fn profitable(&self) -> bool {
+        self.best().is_some_and(|best| best.net_profit >= self.min_profit)
+    }
+}
+
+impl RouteQuote {
+    fn new(direction: String, legs: &[Leg], net_profit: Decimal) -> RouteQuote {
+        let f = |value: Decimal| value.to_f64().unwrap_or(0.0);
+        RouteQuote {
+            direction,
+            quote_symbol: legs.last().map(|leg| leg.token_out.clone()).unwrap_or_default(),
+            amount_in: legs.first().map_or(0.0, |leg| f(leg.amount_in)),
+            amount_out: legs.last().map_or(0.0, |leg| f(leg.amount_out)),
+            net_profit: f(net_profit),
+        }
+    }
+}
+
+/// Result of the optimal trade size search for one route.
+#[derive(Debug, Clone)]
+pub struct Sizing {
+    /// Input amount in the quote token that maximises net profit
+    pub optimal_amount: Decimal,
+    pub max_profit: Decimal,
+    /// `(amount_in, profit)` samples across the search range
+    pub curve: Vec<(Decimal, Decimal)>,
+    /// Whether the optimum came from the constant-product closed form rather than a search
+    pub closed_form: bool,
+}
+
+/// Quote every ordered pair of venues and rank all N×(N−1) buy/sell combinations by profit (best first).
+/// - Buy the base token on each venue using `pair.trade_amount` (path = [QUOTE, BASE]); this is one quote per venue
+/// - Sell it on every other venue (path = [BASE, QUOTE])
+///
+/// Profit = quote_received - trade_amount - gas, with gas priced for the two venues' swap types.
+/// Every quote is pinned to `head`. Each venue is also probed once per direction with a tiny amount
+/// to read its mid price, from which the legs' price impact follows.
+/// Failed quotes are logged and their routes left out, so the list is empty if nothing could be simulated.
+///
+/// With `optimize_size` the best route is also sized with `optimal_size`, and re-quoted there when
+/// that pays more, so a route that loses money at `trade_amount` can still be reported when a smaller
+/// or larger trade pays.
+pub async fn rank_routes(
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    pair: &PairConfig,
+    gas: &GasCosts,
+    optimize_size: bool,
+    head: Head,
+) -> Result<Vec<Route>> {
+    let base = tokens.get(pair.base).await?;
+    let quote = tokens.get(pair.quote).await?;
+    let trade_amount = Decimal::from_f64(pair.trade_amount)
+        .ok_or_else(|| anyhow!("{}: trade amount {} is not representable", pair.name, pair.trade_amount))?;
+    // amount_in for buy: trade_amount in quote token base units
+    let amount_in_buy = quote.to_base_units(trade_amount)?;
+    let path_buy = vec![pair.quote, pair.base];
+    let path_sell = vec![pair.base, pair.quote];
+    let block = head.id();
+
+    // All buys, then all sells, are issued together so a batching venue sends each phase as one call
+    let buy_probe = triangular::probe_amount(amount_in_buy);
+    let (buys, buy_probes) = tokio::join!(
+        join_all(venues.iter().map(|buy| buy.quote(amount_in_buy, &path_buy, block))),
+        probe(venues, buy_probe, &path_buy, block),
+    );
+    let mut combos = Vec::new();
+    for (buy, base_received) in venues.iter().zip(buys) {
+        match base_received {
+            Ok(base_received) => {
+                for sell in venues.iter().filter(|sell| !Arc::ptr_eq(sell, buy)) {
+                    combos.push((buy, sell, base_received));
+                }
+            }
+            Err(e) => eprintln!("{} {} buy quote failed: {:?}", pair.name, buy.name(), e),
         }
-    };
-    
-    let profit_ba = match res_ba {
-        Ok((dir, p)) => Some((dir, p)),
-        Err(e) => {
-            eprintln!("B->A simulation failed: {:?}", e);
-            None
+    }
+    let largest_buy = combos.iter().map(|(_, _, base_received)| *base_received).max().unwrap_or_default();
+    let sell_probe = triangular::probe_amount(largest_buy);
+    let (sells, sell_probes) = tokio::join!(
+        join_all(combos.iter().map(|(_, sell, base_received)| sell.quote(*base_received, &path_sell, block))),
+        probe(venues, sell_probe, &path_sell, block),
+    );
+
+    let mut routes = Vec::new();
+    for ((buy, sell, base_received), quote_received) in combos.into_iter().zip(sells) {
+        let direction = format!("{}->{}", buy.name(), sell.name());
+        let quote_received = match quote_received {
+            Ok(quote_received) => quote_received,
+            Err(e) => {
+                eprintln!("{} {direction} sell quote failed: {:?}", pair.name, e);
+                continue;
+            }
+        };
+        // One route's missing or zero mid price only leaves that route out
+        let route = (|| {
+            let (Some(buy_out), Some(sell_out)) = (buy_probes[buy.name()], sell_probes[sell.name()]) else {
+                return Err(anyhow!("no mid price probe"));
+            };
+            let legs = vec![
+                Leg::new(buy.as_ref(), &quote, &base, (amount_in_buy, base_received), (buy_probe, buy_out))?,
+                Leg::new(sell.as_ref(), &base, &quote, (base_received, quote_received), (sell_probe, sell_out))?,
+            ];
+            let gas_cost = gas.cost(pair.quote, &[buy.kind(), sell.kind()]);
+            Ok(Route {
+                pair: pair.name.clone(),
+                buy_venue: buy.name().to_string(),
+                sell_venue: sell.name().to_string(),
+                base_symbol: base.symbol.clone(),
+                quote_symbol: quote.symbol.clone(),
+                profit: quote.to_human(quote_received)? - trade_amount - gas_cost,
+                sizing: None,
+                optimal_legs: None,
+                gas_cost,
+                legs,
+                block: head,
+            })
+        })();
+        match route {
+            Ok(route) => routes.push(route),
+            Err(e) => eprintln!("{} {direction} route skipped: {:?}", pair.name, e),
         }
-    };
-
-    match (profit_ab, profit_ba) {
-        (Some((dir_ab, profit_ab)), Some((dir_ba, profit_ba))) => {
-            if profit_ab >= profit_ba {
-                Ok(Some((format!("A->B ({})", dir_ab), profit_ab)))
-            } else {
-                Ok(Some((format!("B->A ({})", dir_ba), profit_ba)))
+    }
+
+    routes.sort_by_key(|route| std::cmp::Reverse(route.profit));
+
+    if optimize_size {
+        if let Some(best) = routes.first_mut() {
+            let by_name = |name: &str| venues.iter().find(|venue| venue.name() == name).cloned();
+            if let (Some(buy), Some(sell)) = (by_name(&best.buy_venue), by_name(&best.sell_venue)) {
+                match optimal_size(buy.as_ref(), sell.as_ref(), pair, &quote, best.gas_cost, block).await {
+                    Ok(sizing) => {
+                        // Record the trade at the size that pays, quoted like the fixed one
+                        if sizing.max_profit > best.profit && !sizing.optimal_amount.is_zero() {
+                            let legs = async {
+                                let amount_in = quote.to_base_units(sizing.optimal_amount)?;
+                                let base_received = buy.quote(amount_in, &path_buy, block).await?;
+                                let quote_received = sell.quote(base_received, &path_sell, block).await?;
+                                let (Some(buy_out), Some(sell_out)) = (buy_probes[buy.name()], sell_probes[sell.name()]) else {
+                                    return Err(anyhow!("no mid price probe"));
+                                };
+                                Ok(vec![
+                                    Leg::new(buy.as_ref(), &quote, &base, (amount_in, base_received), (buy_probe, buy_out))?,
+                                    Leg::new(sell.as_ref(), &base, &quote, (base_received, quote_received), (sell_probe, sell_out))?,
+                                ])
+                            };
+                            match legs.await {
+                                Ok(legs) => best.optimal_legs = Some(legs),
+                                Err(e) => eprintln!("{} {} quote at the optimal size failed: {:?}", pair.name, best.direction(), e),
+                            }
+                        }
+                        best.sizing = Some(sizing);
+                    }
+                    Err(e) => eprintln!("{} {} size search failed: {:?}", pair.name, best.direction(), e),
+                }
             }
         }
-        (Some(profit), None) | (None, Some(profit)) => Ok(Some(profit)),
-        (None, None) => Ok(None),
     }
-}
\ No newline at end of file
+    Ok(routes)
+}
+
+/// Marginal-price probe of `amount_in` along `path` on every venue, keyed by venue name.
+/// Failed probes are logged and `None`.
+async fn probe(venues: &[Arc<dyn Dex>], amount_in: U256, path: &[Address], block: BlockId) -> HashMap<String, Option<U256>> {
+    let outs = join_all(venues.iter().map(|venue| venue.quote(amount_in, path, block))).await;
+    venues
+        .iter()
+        .zip(outs)
+        .map(|(venue, out)| {
+            let out = out
+                .inspect_err(|e| eprintln!("{} mid price probe failed: {:?}", venue.name(), e))
+                .ok();
+            (venue.name().to_string(), out)
+        })
+        .collect()
+}
+
+/// Net profit of buying the base token with `amount` of the quote token on `buy` and selling it on `sell`, at `block`.
+pub async fn route_profit(
+    buy: &dyn Dex,
+    sell: &dyn Dex,
+    pair: &PairConfig,
+    quote: &TokenInfo,
+    amount: Decimal,
+    gas_usdc: Decimal,
+    block: BlockId,
+) -> Result<Decimal> {
+    let base_received = buy.quote(quote.to_base_units(amount)?, &[pair.quote, pair.base], block).await?;
+    let quote_received = sell.quote(base_received, &[pair.base, pair.quote], block).await?;
+    Ok(quote.to_human(quote_received)? - amount - gas_usdc)
+}
+
+/// Find the input amount in `(0, pair.max_trade_amount]` that maximises `route_profit`.
+///
+/// When both venues expose constant-product reserves the optimum is solved in closed form and the
+/// optimum and profit curve are priced locally from those reserves, so the whole search costs one
+/// reserves read per venue. Otherwise a golden-section search runs over the quote function (profit
+/// is unimodal in size for AMM curves) and everything is priced with real quotes.
+pub async fn optimal_size(
+    buy: &dyn Dex,
+    sell: &dyn Dex,
+    pair: &PairConfig,
+    quote: &TokenInfo,
+    gas_usdc: Decimal,
+    block: BlockId,
+) -> Result<Sizing> {
+    let max = Decimal::from_f64(pair.max_trade_amount)
+        .ok_or_else(|| anyhow!("{}: max trade amount {} is not representable", pair.name, pair.max_trade_amount))?;
+    let curve_amounts: Vec<Decimal> = (1..=CURVE_POINTS)
+        .map(|i| max * Decimal::from(i) / Decimal::from(CURVE_POINTS))
+        .collect();
+
+    let reserves = (
+        buy.reserves(pair.quote, pair.base, block).await?,
+        sell.reserves(pair.base, pair.quote, block).await?,
+        buy.fee(),
+        sell.fee(),
+    );
+    if let (Some(buy_reserves), Some(sell_reserves), Some(buy_fee), Some(sell_fee)) = reserves {
+        let local_profit = |amount: Decimal| -> Result<Decimal> {
+            let base_out = dex::get_amount_out(quote.to_base_units(amount)?, buy_reserves.0, buy_reserves.1, buy_fee)?;
+            let quote_out = dex::get_amount_out(base_out, sell_reserves.0, sell_reserves.1, sell_fee)?;
+            Ok(quote.to_human(quote_out)? - amount - gas_usdc)
+        };
+        let unit = 10f64.powi(quote.decimals as i32);
+        let optimal_amount = optimal_input_v2(buy_reserves, buy_fee, sell_reserves, sell_fee)
+            .and_then(|x| Decimal::from_f64(x / unit))
+            .unwrap_or(Decimal::ZERO)
+            .round_dp(quote.decimals as u32)
+            .min(max);
+        let max_profit = if optimal_amount.is_zero() { -gas_usdc } else { local_profit(optimal_amount)? };
+        let curve = curve_amounts
+            .into_iter()
+            .map(|amount| Ok((amount, local_profit(amount)?)))
+            .collect::<Result<_>>()?;
+        return Ok(Sizing { optimal_amount, max_profit, curve, closed_form: true });
+    }
+
+    let profit_at = |amount: Decimal| route_profit(buy, sell, pair, quote, amount, gas_usdc, block);
+    let optimal_amount = golden_section(profit_at, max / Decimal::from(1000), max).await?;
+    let max_profit = profit_at(optimal_amount).await?;
+    let mut curve = Vec::with_capacity(CURVE_POINTS);
+    for amount in curve_amounts {
+        curve.push((amount, profit_at(amount).await?));
+    }
+    Ok(Sizing { optimal_amount, max_profit, curve, closed_form: false })
+}
+
+/// Profit-maximising input for a buy on pool 1 followed by a sell on pool 2, both constant-product.
+/// Reserves are `(reserve_in, reserve_out)` in base units, fees in hundredths of a bip.
+///
+/// The two swaps compose to out(x) = a·x / (b + c·x) with a = g1·g2·R1out·R2out, b = R1in·R2in and
+/// c = g1·(R2in + g2·R1out), so d/dx [out(x) − x] = 0 gives x* = (√(a·b) − b) / c.
+/// Returns `None` when even the first unit loses money (a ≤ b).
+pub fn optimal_input_v2(buy: (U256, U256), buy_fee: u32, sell: (U256, U256), sell_fee: u32) -> Option<f64> {
+    let f = |v: U256| v.to_string().parse::<f64>().unwrap_or(0.0);
+    let (r1_in, r1_out, r2_in, r2_out) = (f(buy.0), f(buy.1), f(sell.0), f(sell.1));
+    let g1 = 1.0 - buy_fee as f64 / 1_000_000.0;
+    let g2 = 1.0 - sell_fee as f64 / 1_000_000.0;
+
+    let a = g1 * g2 * r1_out * r2_out;
+    let b = r1_in * r2_in;
+    let c = g1 * (r2_in + g2 * r1_out);
+    if a <= b || c <= 0.0 {
+        return None;
+    }
+    Some(((a * b).sqrt() - b) / c)
+}
+
+/// Maximise a unimodal async function on `[lo, hi]` by golden-section search.
+async fn golden_section<F, Fut>(f: F, lo: Decimal, hi: Decimal) -> Result<Decimal>
+where
+    F: Fn(Decimal) -> Fut,
+    Fut: Future<Output = Result<Decimal>>,
+{
+    let phi = Decimal::from_f64((5f64.sqrt() - 1.0) / 2.0).unwrap();
+    let (mut a, mut b) = (lo, hi);
+    let mut c = b - phi * (b - a);
+    let mut d = a + phi * (b - a);
+    let mut fc = f(c).await?;
+    let mut fd = f(d).await?;
+
+    for _ in 0..SIZING_ITERATIONS {
+        if fc > fd {
+            b = d;
+            d = c;
+            fd = fc;
+            c = b - phi * (b - a);
+            fc = f(c).await?;
+        } else {
+            a = c;
+            c = d;
+            fc = fd;
+            d = a + phi * (b - a);
+            fd = f(d).await?;
+        }
+    }
+    Ok(if fc > fd { c } else { d })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GasConfig;
+    use crate::errors::SimError;
+    use crate::mock::{self, MockDex};
+    use crate::token::from_base_units;
+
+    const GAS: f64 = 0.5;
+
+    fn weth() -> Address {
+        Address::repeat_byte(0xee)
+    }
+
+    fn usdc() -> Address {
+        Address::repeat_byte(0xcc)
+    }
+
+    fn pair(min_profit: f64) -> PairConfig {
+        PairConfig {
+            name: "WETH/USDC".to_string(),
+            base: weth(),
+            quote: usdc(),
+            trade_amount: 1000.0,
+            min_profit,
+            max_trade_amount: 10_000.0,
+        }
+    }
+
+    fn gas() -> GasCosts {
+        GasCosts::fixed(&GasConfig { wrapped_native: Address::zero(), price_venue: None, flash_loan: false, fallback: GAS })
+    }
+
+    fn tokens() -> TokenRegistry {
+        mock::tokens(&[(weth(), "WETH", 18), (usdc(), "USDC", 6)])
+    }
+
+    /// Venue pricing WETH at `price` USDC with 1000 WETH of liquidity and a 0.3% fee
+    fn venue(name: &str, price: u64) -> Arc<MockDex> {
+        Arc::new(MockDex::new(name, 3000).with_pool(weth(), usdc(), mock::units(1000, 18), mock::units(1000 * price, 6)))
+    }
+
+    async fn rank(venues: &[Arc<MockDex>], pair: &PairConfig) -> Result<Vec<Route>> {
+        let venues: Vec<Arc<dyn Dex>> = venues.iter().map(|venue| venue.clone() as Arc<dyn Dex>).collect();
+        rank_routes(&venues, &tokens(), pair, &gas(), false, mock::head(100)).await
+    }
+
+    /// Net profit of 1000 USDC bought into WETH at `buy` and sold at `sell`, by the router formula
+    fn expected_profit(buy: u64, sell: u64) -> Decimal {
+        let weth = dex::get_amount_out(mock::units(1000, 6), mock::units(1000 * buy, 6), mock::units(1000, 18), 3000).unwrap();
+        let usdc = dex::get_amount_out(weth, mock::units(1000, 18), mock::units(1000 * sell, 6), 3000).unwrap();
+        from_base_units(usdc, 6).unwrap() - Decimal::from(1000) - Decimal::from_f64(GAS).unwrap()
+    }
+
+    #[tokio::test]
+    async fn buys_on_the_cheap_venue_and_sells_on_the_dear_one() {
+        let routes = rank(&[venue("Dear", 2100), venue("Cheap", 2000)], &pair(5.0)).await.unwrap();
+
+        let directions: Vec<String> = routes.iter().map(Route::direction).collect();
+        assert_eq!(directions, vec!["Cheap->Dear", "Dear->Cheap"]);
+        assert_eq!(routes[0].profit, expected_profit(2000, 2100));
+        assert_eq!(routes[1].profit, expected_profit(2100, 2000));
+        assert!(routes[0].profit > Decimal::from(40) && routes[1].profit < Decimal::ZERO);
+        assert_eq!(routes[0].gas_cost, Decimal::from_f64(GAS).unwrap());
+        assert_eq!(routes[0].block.number, 100.into());
+    }
+
+    #[tokio::test]
+    async fn failing_venues_are_left_out_of_every_route() {
+        let broken = venue("Broken", 1000);
+        broken.fail_with(SimError::Transport("connection refused".to_string()));
+        let routes = rank(&[venue("Dear", 2100), broken.clone(), venue("Cheap", 2000)], &pair(5.0)).await.unwrap();
+
+        let directions: Vec<String> = routes.iter().map(Route::direction).collect();
+        assert_eq!(directions, vec!["Cheap->Dear", "Dear->Cheap"]);
+
+        // The cheapest venue of all wins once it answers again
+        broken.recover();
+        let routes = rank(&[venue("Dear", 2100), broken, venue("Cheap", 2000)], &pair(5.0)).await.unwrap();
+        assert_eq!(routes[0].direction(), "Broken->Dear");
+    }
+
+    #[tokio::test]
+    async fn a_venue_without_a_mid_price_only_drops_the_routes_buying_there() {
+        // So little WETH that the buy probe rounds down to nothing, while the trade itself still gets some
+        let thin = Arc::new(MockDex::new("Thin", 3000).with_pool(weth(), usdc(), U256::exp10(6), mock::units(1_000_000, 6)));
+        let routes = rank(&[venue("Dear", 2100), thin, venue("Cheap", 2000)], &pair(5.0)).await.unwrap();
+
+        assert!(routes.iter().all(|route| route.buy_venue != "Thin"));
+        assert_eq!(routes.len(), 4);
+        assert!(routes.iter().any(|route| route.direction() == "Cheap->Dear"));
+    }
+
+    #[tokio::test]
+    async fn a_venue_without_the_pool_only_drops_its_own_routes() {
+        let other = Address::repeat_byte(0x11);
+        let elsewhere = Arc::new(MockDex::new("Elsewhere", 3000).with_pool(weth(), other, mock::units(1, 18), mock::units(1, 18)));
+        let routes = rank(&[venue("Dear", 2100), elsewhere, venue("Cheap", 2000)], &pair(5.0)).await.unwrap();
+        assert_eq!(routes.len(), 2);
+        assert!(routes.iter().all(|route| route.buy_venue != "Elsewhere" && route.sell_venue != "Elsewhere"));
+    }
+
+    #[tokio::test]
+    async fn nothing_is_found_when_every_venue_fails() {
+        let (dear, cheap) = (venue("Dear", 2100), venue("Cheap", 2000));
+        dear.fail_with(SimError::RateLimited("429".to_string()));
+        cheap.fail_with(SimError::Revert("INSUFFICIENT_LIQUIDITY".to_string()));
+        let routes = rank(&[dear, cheap], &pair(5.0)).await.unwrap();
+        assert!(routes.is_empty());
+
+        let evaluation = Evaluation::from_routes("WETH/USDC", &routes, Decimal::from(5), mock::head(100)).unwrap();
+        assert!(evaluation.best().is_none());
+        assert!(evaluation.spread_pct().is_none());
+        assert!(!evaluation.profitable());
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_fail_the_pair() {
+        let mut pair = pair(5.0);
+        pair.base = Address::repeat_byte(0x99);
+        assert!(rank(&[venue("Dear", 2100), venue("Cheap", 2000)], &pair).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn the_threshold_is_net_of_gas_and_inclusive() {
+        let routes = rank(&[venue("Dear", 2100), venue("Cheap", 2000)], &pair(5.0)).await.unwrap();
+        let profit = routes[0].profit;
+        let profitable = |min_profit: Decimal| {
+            Evaluation::from_routes("WETH/USDC", &routes, min_profit, mock::head(100)).unwrap().profitable()
+        };
+        // f64 rounding of the recorded profit aside, exactly at the threshold still counts
+        let at = Decimal::from_f64(profit.to_f64().unwrap()).unwrap();
+        assert!(profitable(at));
+        assert!(!profitable(at + Decimal::new(1, 6)));
+        // Gross profit minus gas, not gross profit, is held to the threshold
+        assert!(!profitable(profit + Decimal::from_f64(GAS).unwrap() / Decimal::TWO));
+
+        let robust = Opportunity::from_route(&routes[0], 30, profit - Decimal::from(10)).unwrap();
+        let fragile = Opportunity::from_route(&routes[0], 30, profit).unwrap();
+        assert!(robust.robust && !fragile.robust);
+        assert_eq!(robust.net_profit, profit.to_f64().unwrap());
+        assert_eq!(robust.id, "100:WETH/USDC:Cheap->Dear");
+        assert_eq!(robust.path, vec!["USDC", "WETH", "USDC"]);
+    }
+
+    #[tokio::test]
+    async fn a_route_that_only_pays_at_its_optimal_size_is_recorded_at_that_size() {
+        // Shallow pools: 1000 USDC moves both prices too far, a few hundred clears the threshold
+        let shallow = |name: &str, price: u64| {
+            let venue = MockDex::new(name, 3000).with_pool(weth(), usdc(), mock::units(10, 18), mock::units(10 * price, 6));
+            Arc::new(venue) as Arc<dyn Dex>
+        };
+        let venues = [shallow("Cheap", 2000), shallow("Dear", 2200)];
+        let routes = rank_routes(&venues, &tokens(), &pair(5.0), &gas(), true, mock::head(100)).await.unwrap();
+        let best = &routes[0];
+        assert_eq!(best.direction(), "Cheap->Dear");
+        assert!(best.profit < Decimal::ZERO, "{}", best.profit);
+        assert!(best.best_profit() > Decimal::from(5), "{}", best.best_profit());
+
+        let opportunity = Opportunity::from_route(best, 30, Decimal::from(5)).unwrap();
+        let optimal = best.sizing.as_ref().unwrap().optimal_amount;
+        assert!(optimal > Decimal::from(100) && optimal < Decimal::from(1000), "{optimal}");
+        assert_eq!(opportunity.amount_in, optimal.to_f64().unwrap());
+        assert_eq!(opportunity.optimal_amount, Some(opportunity.amount_in));
+        assert_eq!(opportunity.net_profit, best.best_profit().to_f64().unwrap());
+        assert_eq!(opportunity.optimal_profit, Some(opportunity.net_profit));
+        assert_eq!(opportunity.legs[0].amount_in, opportunity.amount_in);
+        assert_eq!(opportunity.exact.unwrap().0, optimal);
+
+        let evaluation = Evaluation::from_routes("WETH/USDC", &routes, Decimal::from(5), mock::head(100)).unwrap();
+        assert!(evaluation.profitable());
+        assert_eq!(evaluation.best().unwrap().amount_in, opportunity.amount_in);
+        assert_eq!(evaluation.best().unwrap().amount_out, opportunity.amount_out);
+    }
+}
@@ -0,0 +1,432 @@
+use ethers::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use crate::arbitrage::{Evaluation, Opportunity};
+use crate::blocks::Head;
+use crate::config::Config;
+use crate::dex::Dex;
+use crate::rpc::Client;
+use crate::sinks::Sink;
+use crate::token::TokenRegistry;
+
+/// Multiples of each pair's own minimum profit the threshold sensitivity is reported at
+const THRESHOLD_MULTIPLES: [f64; 7] = [0.0, 0.25, 0.5, 1.0, 1.5, 2.0, 5.0];
+
+/// Blocks a backtest walks: every `step`th block from `from` to `to`, inclusive.
+#[derive(Debug, Clone, Copy)]
+pub struct Range {
+    pub from: u64,
+    pub to: u64,
+    pub step: u64,
+}
+
+impl Range {
+    /// Parse `backtest FROM TO [STEP]` from the command line arguments; `None` for a live run.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<Range>> {
+        const USAGE: &str = "usage: backtest FROM TO [STEP]";
+        match args.next().as_deref() {
+            None => return Ok(None),
+            Some("backtest") => {}
+            Some(other) => bail!("unknown command `{other}`; {USAGE}"),
+        }
+        let mut number = |name: &str| -> Result<Option<u64>> {
+            args.next()
+                .map(|arg| arg.parse().with_context(|| format!("{name} must be a block number; {USAGE}")))
+                .transpose()
+        };
+        let (Some(from), Some(to)) = (number("FROM")?, number("TO")?) else {
+            bail!(USAGE);
+        };
+        let step = number("STEP")?.unwrap_or(1);
+        if from > to || step == 0 {
+            bail!("FROM must not be after TO and STEP must be at least 1; {USAGE}");
+        }
+        Ok(Some(Range { from, to, step }))
+    }
+
+    fn blocks(&self) -> impl Iterator<Item = u64> {
+        (self.from..=self.to).step_by(self.step as usize)
+    }
+}
+
+/// What a backtest found over its block range.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub step: u64,
+    pub blocks_evaluated: usize,
+    /// Blocks that could not be fetched or evaluated
+    pub blocks_failed: usize,
+    pub evaluations: usize,
+    pub opportunities: usize,
+    /// Net profit of the opportunities, per quote token
+    pub profit: BTreeMap<String, Distribution>,
+    pub by_pair: BTreeMap<String, Breakdown>,
+    /// Every venue that quoted a leg of an opportunity
+    pub by_venue: BTreeMap<String, Breakdown>,
+    /// How many evaluations would have cleared each multiple of their minimum profit
+    pub thresholds: Vec<Sensitivity>,
+}
+
+/// Spread of a set of profits.
+#[derive(Debug, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub total: f64,
+    pub mean: f64,
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p90: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Breakdown {
+    pub opportunities: usize,
+    /// Opportunities still above their threshold at `SLIPPAGE_BPS`
+    pub robust: usize,
+    /// Blocks with at least one opportunity
+    pub blocks: usize,
+    /// Net profit per quote token
+    pub profit: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Sensitivity {
+    /// Threshold as a multiple of each evaluation's own minimum profit; 0 means any profit after gas
+    pub multiple: f64,
+    pub evaluations: usize,
+    pub blocks: usize,
+}
+
+/// Everything the evaluation produced, kept for the report.
+#[derive(Default)]
+struct Recorded {
+    opportunities: Vec<Opportunity>,
+    evaluations: Vec<Evaluation>,
+}
+
+/// Collects opportunities and evaluations in memory instead of the live logs, database and dashboard.
+struct ReportSink(Arc<Mutex<Recorded>>);
+
+impl Sink for ReportSink {
+    fn record(&mut self, opportunity: &Opportunity) -> Result<()> {
+        self.0.lock().unwrap().opportunities.push(opportunity.clone());
+        Ok(())
+    }
+
+    fn evaluation(&mut self, evaluation: &Evaluation) -> Result<()> {
+        self.0.lock().unwrap().evaluations.push(evaluation.clone());
+        Ok(())
+    }
+}
+
+/// Re-run the evaluation pinned to every block in `range`, then write the report to
+/// `cfg.backtest_report` and print a summary.
+pub async fn run(cfg: &Config, provider: &Client, venues: &[Arc<dyn Dex>], tokens: &TokenRegistry, range: Range) -> Result<()> {
+    // Every evaluation counts towards the threshold sensitivity, not a sample
+    let cfg = Config { evaluation_sample: 1, ..cfg.clone() };
+    let recorded = Arc::new(Mutex::new(Recorded::default()));
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(ReportSink(recorded.clone()))];
+
+    let started = Instant::now();
+    let (mut evaluated, mut failed) = (0, 0);
+    for number in range.blocks() {
+        let block = match provider.get_block(number).await {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                eprintln!("Block {number} not found");
+                failed += 1;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Failed to fetch block {number}: {:?}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let head = Head { number: number.into(), hash: block.hash.unwrap_or_default(), seen: Instant::now() };
+        let gas = crate::gas_costs(&cfg, provider, venues, tokens, head).await;
+        match crate::evaluate(&cfg, venues, tokens, &gas, head, &mut sinks).await {
+            Ok(_) => evaluated += 1,
+            Err(e) => {
+                eprintln!("Error evaluating block {number}: {:?}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    let report = Report::new(range, evaluated, failed, &recorded.lock().unwrap());
+    std::fs::write(&cfg.backtest_report, serde_json::to_string_pretty(&report)?)?;
+    println!(
+        "📈 Backtest of blocks {}..={} (step {}) in {}s: {} evaluated, {} failed, {} evaluations, {} opportunities",
+        range.from,
+        range.to,
+        range.step,
+        started.elapsed().as_secs(),
+        report.blocks_evaluated,
+        report.blocks_failed,
+        report.evaluations,
+        report.opportunities
+    );
+    for (quote, profit) in &report.profit {
+        println!(
+            "   {quote}: total {:.4} mean {:.4} median {:.4} p90 {:.4} max {:.4}",
+            profit.total, profit.mean, profit.median, profit.p90, profit.max
+        );
+    }
+    for (pair, breakdown) in &report.by_pair {
+        println!("   {pair}: {} opportunities in {} blocks", breakdown.opportunities, breakdown.blocks);
+    }
+    for sensitivity in &report.thresholds {
+        println!(
+            "   {}× min profit: {} evaluations in {} blocks",
+            sensitivity.multiple, sensitivity.evaluations, sensitivity.blocks
+        );
+    }
+    println!("Report written to {}", cfg.backtest_report);
+    Ok(())
+}
+
+impl Report {
+    fn new(range: Range, blocks_evaluated: usize, blocks_failed: usize, recorded: &Recorded) -> Report {
+        let mut profits: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        let mut by_pair: BTreeMap<String, (Breakdown, BTreeSet<u64>)> = BTreeMap::new();
+        let mut by_venue: BTreeMap<String, (Breakdown, BTreeSet<u64>)> = BTreeMap::new();
+        for o in &recorded.opportunities {
+            profits.entry(o.quote_symbol.clone()).or_default().push(o.net_profit);
+            let venues: BTreeSet<&str> = o.legs.iter().map(|leg| leg.venue.as_str()).collect();
+            let add = |(breakdown, blocks): &mut (Breakdown, BTreeSet<u64>)| {
+                breakdown.opportunities += 1;
+                breakdown.robust += usize::from(o.robust);
+                *breakdown.profit.entry(o.quote_symbol.clone()).or_default() += o.net_profit;
+                blocks.insert(o.block_number);
+            };
+            add(by_pair.entry(o.pair.clone()).or_default());
+            for venue in venues {
+                add(by_venue.entry(venue.to_string()).or_default());
+            }
+        }
+        let with_blocks = |groups: BTreeMap<String, (Breakdown, BTreeSet<u64>)>| {
+            groups
+                .into_iter()
+                .map(|(name, (breakdown, blocks))| (name, Breakdown { blocks: blocks.len(), ..breakdown }))
+                .collect()
+        };
+
+        let thresholds = THRESHOLD_MULTIPLES
+            .iter()
+            .map(|&multiple| {
+                let clearing: Vec<&Evaluation> = recorded
+                    .evaluations
+                    .iter()
+                    .filter(|e| e.best().is_some_and(|best| best.net_profit >= multiple * e.min_profit))
+                    .collect();
+                let blocks: BTreeSet<u64> = clearing.iter().map(|e| e.block_number).collect();
+                Sensitivity { multiple, evaluations: clearing.len(), blocks: blocks.len() }
+            })
+            .collect();
+
+        Report {
+            from_block: range.from,
+            to_block: range.to,
+            step: range.step,
+            blocks_evaluated,
+            blocks_failed,
+            evaluations: recorded.evaluations.len(),
+            opportunities: recorded.opportunities.len(),
+            profit: profits
+                .into_iter()
+                .filter_map(|(quote, values)| Some((quote, Distribution::of(values)?)))
+                .collect(),
+            by_pair: with_blocks(by_pair),
+            by_venue: with_blocks(by_venue),
+            thresholds,
+        }
+    }
+}
+
+impl Distribution {
+    /// Distribution of a set of values; `None` when there are none.
+    fn of(mut values: Vec<f64>) -> Option<Distribution> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        // Nearest rank
+        let percentile = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        let total: f64 = values.iter().sum();
+        Some(Distribution {
+            count: values.len(),
+            total,
+            mean: total / values.len() as f64,
+            min: values[0],
+            p25: percentile(0.25),
+            median: percentile(0.5),
+            p75: percentile(0.75),
+            p90: percentile(0.9),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arbitrage::{LegQuote, RouteQuote};
+
+    fn args(args: &[&str]) -> Result<Option<Range>> {
+        Range::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn leg(venue: &str) -> LegQuote {
+        LegQuote {
+            venue: venue.to_string(),
+            token_in: "USDC".to_string(),
+            token_out: "WETH".to_string(),
+            amount_in: 1000.0,
+            amount_out: 0.5,
+            mid_price: 0.0005,
+            price_impact: 0.05,
+        }
+    }
+
+    fn opportunity(block: u64, pair: &str, (buy, sell): (&str, &str), net_profit: f64, robust: bool) -> Opportunity {
+        Opportunity {
+            id: format!("{block}:{pair}:{buy}->{sell}"),
+            timestamp: 0,
+            block_number: block,
+            block_hash: String::new(),
+            pair: pair.to_string(),
+            buy_venue: buy.to_string(),
+            sell_venue: sell.to_string(),
+            direction: format!("{buy}->{sell}"),
+            path: vec!["USDC".to_string(), "WETH".to_string(), "USDC".to_string()],
+            amount_in: 1000.0,
+            intermediate_amount: 0.5,
+            amount_out: 1000.0 + net_profit + 1.0,
+            quote_symbol: "USDC".to_string(),
+            gross_profit: net_profit + 1.0,
+            gas_cost: 1.0,
+            net_profit,
+            optimal_amount: None,
+            optimal_profit: None,
+            price_impact: 0.05,
+            slippage_profit: net_profit - 3.0,
+            robust,
+            latency_ms: 0,
+            legs: vec![leg(buy), leg(sell)],
+            exact: None,
+        }
+    }
+
+    fn evaluation(block: u64, best_profit: Option<f64>) -> Evaluation {
+        Evaluation {
+            timestamp: 0,
+            block_number: block,
+            pair: "WETH/USDC".to_string(),
+            min_profit: 5.0,
+            quotes: best_profit
+                .map(|net_profit| RouteQuote {
+                    direction: "A->B".to_string(),
+                    quote_symbol: "USDC".to_string(),
+                    amount_in: 1000.0,
+                    amount_out: 1000.0 + net_profit,
+                    net_profit,
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parses_a_block_range_with_an_optional_step() {
+        assert!(args(&[]).unwrap().is_none());
+        let range = args(&["backtest", "100", "110"]).unwrap().unwrap();
+        assert_eq!((range.from, range.to, range.step), (100, 110, 1));
+        let range = args(&["backtest", "100", "110", "5"]).unwrap().unwrap();
+        assert_eq!(range.blocks().collect::<Vec<_>>(), vec![100, 105, 110]);
+        // A single block is a valid range
+        assert_eq!(args(&["backtest", "7", "7"]).unwrap().unwrap().blocks().count(), 1);
+    }
+
+    #[test]
+    fn rejects_inverted_empty_and_malformed_ranges() {
+        for invalid in [
+            &["backtest", "110", "100"][..],
+            &["backtest", "100", "110", "0"],
+            &["backtest", "100"],
+            &["backtest"],
+            &["backtest", "100", "latest"],
+            &["replay", "100", "110"],
+        ] {
+            assert!(args(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn distribution_percentiles_use_the_nearest_rank() {
+        let profit = Distribution::of(vec![5.0, 1.0, 4.0, 2.0, 3.0]).unwrap();
+        assert_eq!(profit.count, 5);
+        assert_eq!((profit.total, profit.mean), (15.0, 3.0));
+        assert_eq!((profit.min, profit.p25, profit.median, profit.p75, profit.max), (1.0, 2.0, 3.0, 4.0, 5.0));
+        // (5 - 1) × 0.9 = 3.6 rounds to the 5th value
+        assert_eq!(profit.p90, 5.0);
+    }
+
+    #[test]
+    fn distribution_of_one_sample_is_that_sample() {
+        let profit = Distribution::of(vec![-2.5]).unwrap();
+        assert_eq!(profit.count, 1);
+        for value in [profit.total, profit.mean, profit.min, profit.p25, profit.median, profit.p75, profit.p90, profit.max] {
+            assert_eq!(value, -2.5);
+        }
+    }
+
+    #[test]
+    fn distribution_of_no_samples_is_none() {
+        assert!(Distribution::of(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn report_totals_opportunities_per_pair_venue_and_threshold() {
+        let recorded = Recorded {
+            opportunities: vec![
+                opportunity(100, "WETH/USDC", ("A", "B"), 10.0, true),
+                opportunity(101, "WETH/USDC", ("A", "B"), 6.0, false),
+                opportunity(101, "WMATIC/USDC", ("B", "C"), 8.0, true),
+            ],
+            evaluations: vec![evaluation(100, Some(10.0)), evaluation(101, Some(3.0)), evaluation(101, Some(-1.0)), evaluation(102, None)],
+        };
+        let report = Report::new(Range { from: 100, to: 102, step: 1 }, 3, 1, &recorded);
+
+        assert_eq!((report.blocks_evaluated, report.blocks_failed), (3, 1));
+        assert_eq!((report.evaluations, report.opportunities), (4, 3));
+        let usdc = &report.profit["USDC"];
+        assert_eq!((usdc.count, usdc.total, usdc.median), (3, 24.0, 8.0));
+
+        let weth = &report.by_pair["WETH/USDC"];
+        assert_eq!((weth.opportunities, weth.robust, weth.blocks, weth.profit["USDC"]), (2, 1, 2, 16.0));
+        let b = &report.by_venue["B"];
+        assert_eq!((b.opportunities, b.robust, b.blocks, b.profit["USDC"]), (3, 2, 2, 24.0));
+        assert_eq!(report.by_venue["C"].opportunities, 1);
+
+        // Evaluations clearing 0, 0.25, 0.5, 1, 1.5, 2 and 5 times their minimum profit of 5, inclusive
+        let clearing: Vec<(usize, usize)> = report.thresholds.iter().map(|t| (t.evaluations, t.blocks)).collect();
+        assert_eq!(clearing, vec![(2, 2), (2, 2), (2, 2), (1, 1), (1, 1), (1, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn an_empty_backtest_reports_no_profit() {
+        let report = Report::new(Range { from: 1, to: 1, step: 1 }, 0, 1, &Recorded::default());
+        assert_eq!((report.evaluations, report.opportunities), (0, 0));
+        assert!(report.profit.is_empty() && report.by_pair.is_empty() && report.by_venue.is_empty());
+        assert!(report.thresholds.iter().all(|t| t.evaluations == 0));
+    }
+}
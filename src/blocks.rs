@@ -0,0 +1,118 @@
+use ethers::prelude::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use futures::FutureExt;
+use crate::dex::Dex;
+use crate::rpc::Client;
+
+/// The block an evaluation runs against.
+#[derive(Debug, Clone, Copy)]
+pub struct Head {
+    pub number: U64,
+    pub hash: H256,
+    /// When the block was picked up, to measure evaluation latency
+    pub seen: Instant,
+}
+
+impl Head {
+    /// Pin for every call made while evaluating this block. By hash, so a reorg replacing the
+    /// block makes the calls fail instead of quietly reading its replacement.
+    pub fn id(&self) -> BlockId {
+        BlockId::Hash(self.hash)
+    }
+}
+
+enum Source<'a> {
+    /// `eth_subscribe("newHeads")` over WebSocket
+    Subscription(SubscriptionStream<'a, Ws, Block<H256>>),
+    /// `eth_getBlockByNumber("latest")` every `interval`
+    Poll { provider: Arc<Client>, interval: Duration, polled: bool },
+}
+
+/// New blocks to evaluate, each yielded once: repeats of the last block are dropped, and heads that
+/// queued up while an evaluation was running are skipped in favour of the newest one.
+pub struct Heads<'a> {
+    source: Source<'a>,
+    last: Option<Head>,
+}
+
+impl<'a> Heads<'a> {
+    pub async fn subscribe(ws: &'a Provider<Ws>) -> Result<Self> {
+        let stream = ws.subscribe_blocks().await?;
+        Ok(Heads { source: Source::Subscription(stream), last: None })
+    }
+
+    pub fn poll(provider: Arc<Client>, interval: Duration) -> Self {
+        Heads { source: Source::Poll { provider, interval, polled: false }, last: None }
+    }
+
+    /// Wait for the next block that has not been evaluated yet.
+    pub async fn next(&mut self) -> Result<Head> {
+        loop {
+            let block = match &mut self.source {
+                Source::Subscription(stream) => {
+                    let mut block = stream.next().await.ok_or_else(|| anyhow!("new heads subscription ended"))?;
+                    let mut skipped = 0;
+                    while let Some(Some(newer)) = stream.next().now_or_never() {
+                        block = newer;
+                        skipped += 1;
+                    }
+                    if skipped > 0 {
+                        eprintln!("Skipped {skipped} stale heads while evaluating");
+                    }
+                    block
+                }
+                Source::Poll { provider, interval, polled } => {
+                    if *polled {
+                        tokio::time::sleep(*interval).await;
+                    }
+                    *polled = true;
+                    match provider.get_block(BlockNumber::Latest).await {
+                        Ok(Some(block)) => block,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            eprintln!("Failed to fetch latest block: {:?}", e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            let (Some(number), Some(hash)) = (block.number, block.hash) else {
+                continue;
+            };
+            // A reorg replaces a block at the same height with a new hash, which is worth evaluating
+            if self.last.is_some_and(|last| hash == last.hash || number < last.number) {
+                continue;
+            }
+            let head = Head { number, hash, seen: Instant::now() };
+            self.last = Some(head);
+            return Ok(head);
+        }
+    }
+}
+
+/// V2 pair addresses behind every `(token, token)` link on every venue. Venues without such pools
+/// (V3, Algebra) are left out, so their price moves alone do not trigger an evaluation.
+pub async fn watched_pools(venues: &[Arc<dyn Dex>], links: &[(Address, Address)]) -> Vec<Address> {
+    let mut pools = Vec::new();
+    for (a, b) in links {
+        for venue in venues {
+            match venue.pool(*a, *b).await {
+                Ok(Some(pool)) if !pools.contains(&pool) => pools.push(pool),
+                Ok(_) => {}
+                Err(e) => eprintln!("No pool to watch on {}: {:?}", venue.name(), e),
+            }
+        }
+    }
+    pools
+}
+
+/// Whether any of `pools` emitted `Sync` (a reserves update) in `head`.
+pub async fn has_sync(provider: &Client, head: Head, pools: &[Address]) -> Result<bool> {
+    let filter = Filter::new()
+        .at_block_hash(head.hash)
+        .address(pools.to_vec())
+        .event("Sync(uint112,uint112)");
+    Ok(!provider.get_logs(&filter).await?.is_empty())
+}
@@ -1,58 +1,558 @@
 use dotenv::dotenv;
+use ethers::signers::LocalWallet;
 use ethers::types::Address;
+use std::collections::BTreeMap;
 use std::env;
+use crate::dex::DexKind;
+use crate::multicall::MULTICALL3_ADDRESS;
+
+/// Wrapped MATIC (now WPOL) on Polygon PoS
+const WMATIC_ADDRESS: &str = "0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270";
+
+/// A quoting venue as described in the environment; turned into a `dex::Dex` by `dex::connect`.
+#[derive(Debug, Clone)]
+pub struct VenueConfig {
+    pub name: String,
+    pub kind: DexKind,
+    /// Router address for V2 venues, quoter address for V3/Algebra
+    pub address: Address,
+    /// Fee in hundredths of a bip; `None` uses the protocol default
+    pub fee: Option<u32>,
+    /// V2 only: quote from pair reserves locally instead of calling the router (`LOCAL_AMM`)
+    pub local_math: bool,
+}
+
+/// A watched pair: buy `base` with `trade_amount` of `quote` on one venue and sell it back on another.
+#[derive(Debug, Clone)]
+pub struct PairConfig {
+    /// Label such as `WETH/USDC`, recorded with every opportunity
+    pub name: String,
+    pub base: Address,
+    pub quote: Address,
+    pub trade_amount: f64,
+    pub min_profit: f64,
+    /// Upper bound for the optimal-size search (`OPTIMIZE_SIZE`)
+    pub max_trade_amount: f64,
+}
+
+/// A triangular cycle such as USDC>WETH>WMATIC>USDC, starting and ending in `path[0]`.
+#[derive(Debug, Clone)]
+pub struct CycleConfig {
+    /// Label such as `USDC>WETH>WMATIC>USDC`
+    pub name: String,
+    pub path: Vec<Address>,
+    pub trade_amount: f64,
+    pub min_profit: f64,
+}
+
+/// How opportunities are discovered each cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discovery {
+    /// Rank the two-leg routes of every pair and price the configured cycles
+    Routes,
+    /// Build a token/pool graph from all pairs and cycles and search it for negative cycles
+    Graph,
+}
+
+/// How gas is priced into profits (see `gas::estimate`).
+#[derive(Debug, Clone)]
+pub struct GasConfig {
+    /// Wrapped native token (WMATIC/WPOL) quoted to price gas in each quote token
+    pub wrapped_native: Address,
+    /// Venue the native token is priced on; the first venue when unset
+    pub price_venue: Option<String>,
+    /// Charge the gas of a flash-loan wrapper around every route
+    pub flash_loan: bool,
+    /// Flat cost in the quote token when gas cannot be priced
+    pub fallback: f64,
+}
+
+/// Opt-in execution (`EXECUTE`): where robust opportunities are sent and who signs them.
+#[derive(Debug, Clone)]
+pub struct ExecutionConfig {
+    /// Arbitrage contract swapping every leg atomically (`ARB_CONTRACT`), funded with the quote tokens
+    pub contract: Address,
+    /// Signer of every transaction and owner of the contract (`PRIVATE_KEY`); only its address is printed
+    pub wallet: LocalWallet,
+    /// CSV every execution is appended to; `None` when `EXECUTIONS_LOG` is empty
+    pub log: Option<String>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub rpc_url: String,
-    pub dex1_router: Address,
-    pub dex2_router: Address,
-    pub weth: Address,
-    pub usdc: Address,
-    pub trade_amount_usdc: f64,
-    pub min_profit_usdc: f64,
+    /// HTTP endpoints in order of preference; later ones take over while earlier ones fail
+    pub rpc_urls: Vec<String>,
+    /// File every answer from the endpoints is recorded to (`RECORD_FIXTURE`)
+    pub record_fixture: Option<String>,
+    /// File of recorded answers served instead of the endpoints (`REPLAY_FIXTURE`)
+    pub replay_fixture: Option<String>,
+    /// Endpoints a backtest reads past blocks from (`ARCHIVE_RPC`); `rpc_urls` when unset
+    pub archive_rpc_urls: Vec<String>,
+    /// Requests allowed in flight to each endpoint at once; further quotes queue
+    pub max_concurrency: usize,
+    /// Endpoints that must return the same answer for every quote (`RPC_QUORUM`)
+    pub rpc_quorum: usize,
+    /// WebSocket endpoint; when set, evaluation runs once per new head instead of on a timer
+    pub ws_url: Option<String>,
+    /// Only evaluate blocks in which a watched V2 pair emitted `Sync`
+    pub sync_only: bool,
+    pub venues: Vec<VenueConfig>,
+    pub pairs: Vec<PairConfig>,
+    pub cycles: Vec<CycleConfig>,
+    /// Every token in `TOKENS` by symbol
+    pub tokens: BTreeMap<String, Address>,
+    pub discovery: Discovery,
+    /// Longest cycle the graph search looks for
+    pub max_hops: usize,
+    /// Search for the profit-maximising size of each pair's best route
+    pub optimize_size: bool,
+    pub gas: GasConfig,
+    /// Adverse move (basis points) the minimum-out tolerates; opportunities that stay above
+    /// their threshold at this slippage are reported as robust
+    pub slippage_bps: u32,
+    /// Multicall3 contract quotes are batched through (`MULTICALL3`); `None` when `MULTICALL=false`
+    pub multicall: Option<Address>,
     pub poll_interval_secs: u64,
     pub csv_log: String,
+    /// CSV every evaluation is appended to, profitable or not; `None` when `EVALUATIONS_LOG` is empty
+    pub evaluations_log: Option<String>,
+    /// Record the evaluations of one block in this many; profitable ones are always recorded
+    pub evaluation_sample: u64,
+    /// Where a backtest writes its JSON report
+    pub backtest_report: String,
+    /// SQLite database every block and opportunity is stored in; `None` when `DB_PATH` is empty
+    pub db_path: Option<String>,
+    /// Send robust opportunities to the arbitrage contract; `None` (read-only) unless `EXECUTE=true`
+    pub execution: Option<ExecutionConfig>,
 }
 
 impl Config {
     pub fn from_env() -> Self {
         dotenv().ok();
-        let trade_amount_usdc = env::var("TRADE_AMOUNT_USDC")
+        Self::from_vars(&Vars::Process)
+    }
+
+    /// Only `values`, ignoring the process environment and `.env`; later entries override earlier ones
+    #[cfg(test)]
+    pub fn from_values(values: &[(&str, String)]) -> Self {
+        Self::from_vars(&Vars::Given(values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()))
+    }
+
+    fn from_vars(vars: &Vars) -> Self {
+        // Defaults for pairs that do not set their own trade size / threshold
+        let trade_amount_usdc = vars.var("TRADE_AMOUNT_USDC")
             .unwrap_or_else(|_| "1000".to_string())
             .parse()
             .expect("TRADE_AMOUNT_USDC must be a number");
-        let min_profit_usdc = env::var("MIN_PROFIT_USDC")
+        let min_profit_usdc = vars.var("MIN_PROFIT_USDC")
             .unwrap_or_else(|_| "5".to_string())
             .parse()
             .expect("MIN_PROFIT_USDC must be a number");
-        let poll_interval_secs = env::var("POLL_INTERVAL_SECS")
+        let poll_interval_secs = vars.var("POLL_INTERVAL_SECS")
             .unwrap_or_else(|_| "10".to_string())
             .parse()
             .expect("POLL_INTERVAL_SECS must be a number");
-        
-        Config {
-            rpc_url: env::var("POLYGON_RPC").expect("POLYGON_RPC must be set"),
-            dex1_router: env::var("DEX1_ROUTER")
-                .expect("DEX1_ROUTER must be set")
+        let discovery = match vars.var("DISCOVERY").unwrap_or_else(|_| "routes".to_string()).to_lowercase().as_str() {
+            "routes" => Discovery::Routes,
+            "graph" => Discovery::Graph,
+            other => panic!("DISCOVERY must be routes or graph (got {other})"),
+        };
+        let max_hops = vars.var("MAX_HOPS")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse()
+            .expect("MAX_HOPS must be a number");
+
+        let optimize_size = vars.var("OPTIMIZE_SIZE")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        let multicall = match vars.var("MULTICALL").map(|v| v.to_lowercase()).as_deref() {
+            Ok("false") | Ok("0") => None,
+            _ => Some(
+                vars.var("MULTICALL3")
+                    .unwrap_or_else(|_| MULTICALL3_ADDRESS.to_string())
+                    .parse()
+                    .expect("MULTICALL3 must be an address"),
+            ),
+        };
+
+        let sync_only = vars.var("SYNC_ONLY")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+
+        let gas = GasConfig {
+            wrapped_native: vars.var("WMATIC")
+                .unwrap_or_else(|_| WMATIC_ADDRESS.to_string())
                 .parse()
-                .expect("DEX1_ROUTER must be a valid Ethereum address"),
-            dex2_router: env::var("DEX2_ROUTER")
-                .expect("DEX2_ROUTER must be set")
+                .expect("WMATIC must be an address"),
+            price_venue: vars.var("GAS_PRICE_VENUE").ok().filter(|name| !name.is_empty()),
+            flash_loan: vars.var("FLASH_LOAN")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+            fallback: vars.var("GAS_FALLBACK")
+                .unwrap_or_else(|_| "5".to_string())
                 .parse()
-                .expect("DEX2_ROUTER must be a valid Ethereum address"),
-            weth: env::var("WETH")
-                .expect("WETH must be set")
+                .expect("GAS_FALLBACK must be a number"),
+        };
+
+        let max_concurrency = vars.var("MAX_CONCURRENCY")
+            .unwrap_or_else(|_| "16".to_string())
+            .parse()
+            .expect("MAX_CONCURRENCY must be a number");
+
+        // A replayed run answers every request from the fixture and needs no endpoint
+        let replay_fixture = vars.var("REPLAY_FIXTURE").ok().filter(|path| !path.is_empty());
+        let rpc_urls: Vec<String> = match &replay_fixture {
+            Some(_) => vars.var("POLYGON_RPC").unwrap_or_default(),
+            None => vars.var("POLYGON_RPC").expect("POLYGON_RPC must be set"),
+        }
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+        assert!(
+            !rpc_urls.is_empty() || replay_fixture.is_some(),
+            "POLYGON_RPC must list at least one endpoint"
+        );
+        let archive_rpc_urls: Vec<String> = match vars.var("ARCHIVE_RPC") {
+            Ok(list) => list.split(',').map(str::trim).filter(|url| !url.is_empty()).map(str::to_string).collect(),
+            Err(_) => Vec::new(),
+        };
+        let archive_rpc_urls = if archive_rpc_urls.is_empty() { rpc_urls.clone() } else { archive_rpc_urls };
+        let rpc_quorum = vars.var("RPC_QUORUM")
+            .unwrap_or_else(|_| "1".to_string())
+            .parse()
+            .expect("RPC_QUORUM must be a number");
+        assert!(
+            (1..=rpc_urls.len()).contains(&rpc_quorum) || replay_fixture.is_some(),
+            "RPC_QUORUM must be between 1 and the number of POLYGON_RPC endpoints"
+        );
+
+        let slippage_bps = vars.var("SLIPPAGE_BPS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .expect("SLIPPAGE_BPS must be a whole number of basis points");
+
+        let tokens = tokens_from_env(vars);
+        let pairs = pairs_from_env(vars, &tokens, trade_amount_usdc, min_profit_usdc);
+        let cycles = cycles_from_env(vars, &tokens, trade_amount_usdc, min_profit_usdc);
+
+        let evaluation_sample: u64 = vars.var("EVALUATION_SAMPLE")
+            .unwrap_or_else(|_| "1".to_string())
+            .parse()
+            .expect("EVALUATION_SAMPLE must be a whole number");
+        assert!(evaluation_sample >= 1, "EVALUATION_SAMPLE must be at least 1");
+
+        let execution = vars.var("EXECUTE")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false)
+            .then(|| {
+                assert!(replay_fixture.is_none(), "EXECUTE cannot be used with REPLAY_FIXTURE");
+                ExecutionConfig {
+                    contract: vars.var("ARB_CONTRACT")
+                        .expect("ARB_CONTRACT must be set when EXECUTE is enabled")
+                        .parse()
+                        .expect("ARB_CONTRACT must be an address"),
+                    wallet: vars.var("PRIVATE_KEY")
+                        .expect("PRIVATE_KEY must be set when EXECUTE is enabled")
+                        .parse()
+                        .expect("PRIVATE_KEY must be a hex private key"),
+                    log: Some(vars.var("EXECUTIONS_LOG").unwrap_or_else(|_| "executions.csv".to_string()))
+                        .filter(|path| !path.is_empty()),
+                }
+            });
+
+        Config {
+            rpc_urls,
+            archive_rpc_urls,
+            max_concurrency,
+            rpc_quorum,
+            // Heads come from the fixture too when replaying
+            ws_url: vars.var("POLYGON_WS").ok().filter(|url| !url.is_empty() && replay_fixture.is_none()),
+            record_fixture: vars.var("RECORD_FIXTURE").ok().filter(|path| !path.is_empty()),
+            replay_fixture,
+            sync_only,
+            venues: venues_from_env(vars),
+            pairs,
+            cycles,
+            tokens,
+            discovery,
+            max_hops,
+            optimize_size,
+            gas,
+            slippage_bps,
+            multicall,
+            poll_interval_secs,
+            csv_log: vars.var("CSV_LOG").unwrap_or_else(|_| "arb_log.csv".to_string()),
+            evaluations_log: Some(vars.var("EVALUATIONS_LOG").unwrap_or_else(|_| "evaluations.csv".to_string()))
+                .filter(|path| !path.is_empty()),
+            evaluation_sample,
+            backtest_report: vars.var("BACKTEST_REPORT").unwrap_or_else(|_| "backtest_report.json".to_string()),
+            db_path: Some(vars.var("DB_PATH").unwrap_or_else(|_| "arb.db".to_string())).filter(|path| !path.is_empty()),
+            execution,
+        }
+    }
+}
+
+/// Where configuration values are read from.
+enum Vars {
+    Process,
+    /// Explicit values only, for tests
+    #[cfg(test)]
+    Given(Vec<(String, String)>),
+}
+
+impl Vars {
+    fn var(&self, name: impl AsRef<str>) -> Result<String, env::VarError> {
+        let name = name.as_ref();
+        match self {
+            Vars::Process => env::var(name),
+            #[cfg(test)]
+            Vars::Given(values) => values
+                .iter()
+                .rev()
+                .find(|(given, _)| *given == name)
+                .map(|(_, value)| value.clone())
+                .ok_or(env::VarError::NotPresent),
+        }
+    }
+}
+
+/// Read `TOKENS`, a comma separated list of `SYMBOL:address` entries, plus the legacy `WETH` / `USDC` variables.
+fn tokens_from_env(vars: &Vars) -> BTreeMap<String, Address> {
+    let mut tokens = BTreeMap::new();
+    if let Ok(list) = vars.var("TOKENS") {
+        for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (symbol, address) = entry
+                .split_once(':')
+                .unwrap_or_else(|| panic!("TOKENS entry `{entry}` must be SYMBOL:address"));
+            let address = address
+                .trim()
                 .parse()
-                .expect("WETH must be a valid Ethereum address"),
-            usdc: env::var("USDC")
-                .expect("USDC must be set")
+                .unwrap_or_else(|_| panic!("TOKENS entry `{entry}` has an invalid address"));
+            tokens.insert(symbol.trim().to_string(), address);
+        }
+    }
+    for symbol in ["WETH", "USDC"] {
+        if let Ok(address) = vars.var(symbol) {
+            let address = address
                 .parse()
-                .expect("USDC must be a valid Ethereum address"),
-            trade_amount_usdc,
-            min_profit_usdc,
-            poll_interval_secs,
-            csv_log: env::var("CSV_LOG").unwrap_or_else(|_| "arb_log.csv".to_string()),
+                .unwrap_or_else(|_| panic!("{symbol} must be a valid Ethereum address"));
+            tokens.entry(symbol.to_string()).or_insert(address);
         }
     }
-}
\ No newline at end of file
+    tokens
+}
+
+/// Read `PAIRS`, a comma separated list of `BASE/QUOTE[:trade_amount[:min_profit[:max_trade_amount]]]` entries
+/// using symbols from `TOKENS`, e.g. `WETH/USDC:1000:5:20000,WMATIC/USDC:500:2`. Missing amounts fall back to
+/// `TRADE_AMOUNT_USDC` / `MIN_PROFIT_USDC`, and the size search cap to 10× the trade amount.
+/// Without `PAIRS` only WETH/USDC is watched.
+fn pairs_from_env(vars: &Vars, tokens: &BTreeMap<String, Address>, trade_amount: f64, min_profit: f64) -> Vec<PairConfig> {
+    let list = vars.var("PAIRS").unwrap_or_else(|_| "WETH/USDC".to_string());
+    let pairs: Vec<PairConfig> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.split(':').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let (base, quote) = name
+                .split_once('/')
+                .unwrap_or_else(|| panic!("PAIRS entry `{entry}` must start with BASE/QUOTE"));
+            let token = |symbol: &str| {
+                *tokens
+                    .get(symbol)
+                    .unwrap_or_else(|| panic!("PAIRS entry `{entry}` uses unknown token {symbol}; add it to TOKENS"))
+            };
+            let amount = |value: Option<&str>, default: f64| {
+                value.map_or(default, |v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("PAIRS entry `{entry}` has an invalid amount"))
+                })
+            };
+            let trade_amount = amount(parts.next(), trade_amount);
+            PairConfig {
+                name: name.to_string(),
+                base: token(base),
+                quote: token(quote),
+                trade_amount,
+                min_profit: amount(parts.next(), min_profit),
+                max_trade_amount: amount(parts.next(), trade_amount * 10.0),
+            }
+        })
+        .collect();
+    assert!(!pairs.is_empty(), "at least one pair must be configured");
+    pairs
+}
+
+/// Read `CYCLES`, a comma separated list of `A>B>C>A[:trade_amount[:min_profit]]` entries using symbols
+/// from `TOKENS`, e.g. `USDC>WETH>WMATIC>USDC:1000:2`. Unset means no triangular evaluation.
+fn cycles_from_env(vars: &Vars, tokens: &BTreeMap<String, Address>, trade_amount: f64, min_profit: f64) -> Vec<CycleConfig> {
+    let list = vars.var("CYCLES").unwrap_or_default();
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut parts = entry.split(':').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let path: Vec<Address> = name
+                .split('>')
+                .map(|symbol| {
+                    *tokens
+                        .get(symbol.trim())
+                        .unwrap_or_else(|| panic!("CYCLES entry `{entry}` uses unknown token {symbol}; add it to TOKENS"))
+                })
+                .collect();
+            if path.len() < 3 || path.first() != path.last() {
+                panic!("CYCLES entry `{entry}` must start and end on the same token, e.g. USDC>WETH>WMATIC>USDC");
+            }
+            let amount = |value: Option<&str>, default: f64| {
+                value.map_or(default, |v| {
+                    v.parse()
+                        .unwrap_or_else(|_| panic!("CYCLES entry `{entry}` has an invalid amount"))
+                })
+            };
+            CycleConfig {
+                name: name.to_string(),
+                path,
+                trade_amount: amount(parts.next(), trade_amount),
+                min_profit: amount(parts.next(), min_profit),
+            }
+        })
+        .collect()
+}
+
+/// Read the venue list from `VENUES`, a comma separated list of `name:kind:address[:fee]` entries, e.g.
+/// `QuickSwap:v2:0xa5E0...,SushiSwap:v2:0x1b02...,UniswapV3:v3:0x61fF...:500`.
+/// Without `VENUES` the legacy `DEX1_*` / `DEX2_*` variables are used.
+fn venues_from_env(vars: &Vars) -> Vec<VenueConfig> {
+    let local_math = vars.var("LOCAL_AMM")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+    let mut venues: Vec<VenueConfig> = match vars.var("VENUES") {
+        Ok(list) => list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(parse_venue)
+            .collect(),
+        Err(_) => vec![venue_from_env(vars, "DEX1"), venue_from_env(vars, "DEX2")],
+    };
+    check_venues(&venues);
+    for venue in &mut venues {
+        venue.local_math = local_math;
+    }
+    venues
+}
+
+/// At least two venues, each with its own name: routes, probes and sizing look venues up by name.
+fn check_venues(venues: &[VenueConfig]) {
+    assert!(venues.len() >= 2, "at least two venues must be configured");
+    for (i, venue) in venues.iter().enumerate() {
+        assert!(
+            !venues[..i].iter().any(|other| other.name == venue.name),
+            "venue name {} is configured more than once",
+            venue.name
+        );
+    }
+}
+
+fn parse_venue(entry: &str) -> VenueConfig {
+    let parts: Vec<&str> = entry.split(':').map(str::trim).collect();
+    if parts.len() < 3 || parts.len() > 4 {
+        panic!("VENUES entry `{entry}` must be name:kind:address[:fee]");
+    }
+    VenueConfig {
+        name: parts[0].to_string(),
+        kind: parse_kind(parts[1], "VENUES"),
+        address: parts[2]
+            .parse()
+            .unwrap_or_else(|_| panic!("VENUES entry `{entry}` has an invalid address")),
+        fee: parts.get(3).map(|fee| parse_fee(fee, &format!("VENUES entry `{entry}`"))),
+        local_math: false,
+    }
+}
+
+/// Fee in hundredths of a bip; a fee of 100% or more would leave nothing to swap.
+fn parse_fee(fee: &str, var: &str) -> u32 {
+    let fee: u32 = fee
+        .parse()
+        .unwrap_or_else(|_| panic!("{var} has an invalid fee"));
+    assert!(fee < 1_000_000, "{var} fee must be below 1000000 (100%)");
+    fee
+}
+
+fn parse_kind(kind: &str, var: &str) -> DexKind {
+    match kind.to_lowercase().as_str() {
+        "v2" => DexKind::UniswapV2,
+        "v3" => DexKind::UniswapV3,
+        "algebra" => DexKind::Algebra,
+        other => panic!("{var} kind must be v2, v3 or algebra (got {other})"),
+    }
+}
+
+/// Read `<PREFIX>_NAME` (defaults to the prefix), `<PREFIX>_KIND` (v2 | v3 | algebra, default v2),
+/// `<PREFIX>_ROUTER` and optionally `<PREFIX>_FEE`.
+/// For v3 and algebra venues `<PREFIX>_ROUTER` is the quoter address.
+fn venue_from_env(vars: &Vars, prefix: &str) -> VenueConfig {
+    let name = vars.var(format!("{prefix}_NAME")).unwrap_or_else(|_| prefix.to_string());
+    let kind = parse_kind(
+        &vars.var(format!("{prefix}_KIND")).unwrap_or_else(|_| "v2".to_string()),
+        &format!("{prefix}_KIND"),
+    );
+    let address = vars.var(format!("{prefix}_ROUTER"))
+        .unwrap_or_else(|_| panic!("{prefix}_ROUTER must be set"))
+        .parse()
+        .unwrap_or_else(|_| panic!("{prefix}_ROUTER must be a valid Ethereum address"));
+    let fee = vars.var(format!("{prefix}_FEE")).ok().map(|fee| parse_fee(&fee, &format!("{prefix}_FEE")));
+    VenueConfig { name, kind, address, fee, local_math: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUICKSWAP: &str = "QuickSwap:v2:0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff";
+
+    #[test]
+    fn explicit_values_override_earlier_ones_and_fall_back_to_defaults() {
+        let config = Config::from_values(&[
+            ("POLYGON_RPC", "http://localhost:8545".to_string()),
+            ("TOKENS", "WETH:0x7ceB23fD6bC0adD59E62ac25578270cFf1b9f619,USDC:0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174".to_string()),
+            ("VENUES", "QuickSwap:v2:0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff".to_string()),
+            ("VENUES", format!("{},{}", QUICKSWAP, "SushiSwap:v2:0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506")),
+        ]);
+        assert_eq!(config.rpc_urls, ["http://localhost:8545"]);
+        assert_eq!(config.venues.iter().map(|venue| venue.name.as_str()).collect::<Vec<_>>(), ["QuickSwap", "SushiSwap"]);
+        assert_eq!(config.csv_log, "arb_log.csv");
+        assert!(config.execution.is_none());
+    }
+
+    #[test]
+    fn venue_fees_below_100_percent_are_accepted() {
+        let venue = parse_venue("ApeSwap:v2:0xC0788A3aD43d79aa53B09c2EaCc313A787d1d607:999999");
+        assert_eq!(venue.fee, Some(999_999));
+    }
+
+    #[test]
+    #[should_panic(expected = "fee must be below 1000000")]
+    fn venue_fees_of_100_percent_or_more_are_rejected() {
+        parse_venue("ApeSwap:v2:0xC0788A3aD43d79aa53B09c2EaCc313A787d1d607:1000000");
+    }
+
+    #[test]
+    #[should_panic(expected = "venue name QuickSwap is configured more than once")]
+    fn duplicate_venue_names_are_rejected() {
+        let venues: Vec<VenueConfig> = [
+            "QuickSwap:v2:0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff",
+            "SushiSwap:v2:0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506",
+            "QuickSwap:v3:0x61fFE014bA17989E743c5F6cB21bF9697530B21e",
+        ]
+        .into_iter()
+        .map(parse_venue)
+        .collect();
+        check_venues(&venues);
+    }
+}
@@ -1,27 +1,603 @@
 use ethers::prelude::*;
-use std::sync::Arc;
-use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, bail, ensure, Result};
+use async_trait::async_trait;
+use crate::config::VenueConfig;
+use crate::errors::SimError;
+use crate::rpc::Client;
 
 // Abigen for UniswapV2-style router (getAmountsOut)
 abigen!(
     UniswapV2Router,
     r#"[
         function getAmountsOut(uint256 amountIn, address[] memory path) external view returns (uint256[] memory amounts)
+        function factory() external view returns (address)
     ]"#
 );
 
+// Abigen for UniswapV2-style factory and pair (local constant-product math)
+abigen!(
+    UniswapV2Factory,
+    r#"[
+        function getPair(address tokenA, address tokenB) external view returns (address pair)
+    ]"#
+);
+
+abigen!(
+    UniswapV2Pair,
+    r#"[
+        function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast)
+    ]"#
+);
+
+// Abigen for Uniswap V3 QuoterV2 (fee-tiered pools)
+abigen!(
+    UniswapV3Quoter,
+    r#"[
+        struct QuoteExactInputSingleParams { address tokenIn; address tokenOut; uint256 amountIn; uint24 fee; uint160 sqrtPriceLimitX96; }
+        function quoteExactInputSingle(QuoteExactInputSingleParams memory params) external returns (uint256 amountOut, uint160 sqrtPriceX96After, uint32 initializedTicksCrossed, uint256 gasEstimate)
+        function quoteExactInput(bytes memory path, uint256 amountIn) external returns (uint256 amountOut, uint160[] memory sqrtPriceX96AfterList, uint32[] memory initializedTicksCrossedList, uint256 gasEstimate)
+    ]"#
+);
+
+// Abigen for Algebra quoter (QuickSwap V3, dynamic fee per pool)
+abigen!(
+    AlgebraQuoter,
+    r#"[
+        function quoteExactInputSingle(address tokenIn, address tokenOut, uint256 amountIn, uint160 limitSqrtPrice) external returns (uint256 amountOut, uint16 fee)
+        function quoteExactInput(bytes memory path, uint256 amountIn) external returns (uint256 amountOut, uint16[] memory fees)
+    ]"#
+);
+
+/// Which quoting protocol a venue speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DexKind {
+    /// UniswapV2-style router (`getAmountsOut`)
+    UniswapV2,
+    /// Uniswap V3 QuoterV2 with a fixed fee tier
+    UniswapV3,
+    /// Algebra quoter (QuickSwap V3); the pool fee is dynamic
+    Algebra,
+}
+
+/// A quoting venue. Arbitrage logic is written against this trait, so new venues
+/// only need an implementation here plus a case in `connect`.
+#[async_trait]
+pub trait Dex: Send + Sync {
+    /// Human readable name used in logs and the dashboard
+    fn name(&self) -> &str;
+    fn kind(&self) -> DexKind;
+    /// Swap fee in hundredths of a bip (3000 = 0.3%), or `None` when it is dynamic
+    fn fee(&self) -> Option<u32>;
+    /// Quote `amount_in` of `path[0]` along `path` at `block` and return the amount of the last token
+    async fn quote(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<U256>;
+
+    /// The independent eth_calls `(target, calldata)` that make up `quote`, so they can be batched
+    /// through Multicall3. `None` when the venue cannot express the quote that way (right now).
+    fn quote_calls(&self, _amount_in: U256, _path: &[Address]) -> Option<Vec<(Address, Bytes)>> {
+        None
+    }
+
+    /// Turn the return data of `quote_calls` (same order) into the final output amount.
+    fn decode_quote(&self, _amount_in: U256, _path: &[Address], _results: &[Bytes]) -> Result<U256> {
+        bail!("{} quotes cannot be batched", self.name())
+    }
+
+    /// Constant-product reserves `(reserve_in, reserve_out)` of the pool swapping `token_in` for
+    /// `token_out`, for venues where that is meaningful. Enables closed-form trade sizing.
+    async fn reserves(&self, _token_in: Address, _token_out: Address, _block: BlockId) -> Result<Option<(U256, U256)>> {
+        Ok(None)
+    }
+
+    /// Address of the constant-product pool for `token_a`/`token_b`, whose `Sync` events signal a
+    /// price change. `None` for venues without such a pool.
+    async fn pool(&self, _token_a: Address, _token_b: Address) -> Result<Option<Address>> {
+        Ok(None)
+    }
+
+    /// Amounts after every hop of `path`, starting with `amount_in` (the shape of `getAmountsOut`).
+    /// The default quotes hop by hop; venues that can return all hops in one call override it.
+    async fn quote_hops(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<Vec<U256>> {
+        let mut amounts = vec![amount_in];
+        for hop in path.windows(2) {
+            let amount = *amounts.last().unwrap();
+            amounts.push(self.quote(amount, hop, block).await?);
+        }
+        Ok(amounts)
+    }
+}
+
+/// Build a venue from its config entry.
+pub fn connect(venue: &VenueConfig, provider: Arc<Client>) -> Arc<dyn Dex> {
+    match venue.kind {
+        DexKind::UniswapV2 => Arc::new(UniswapV2Dex {
+            name: venue.name.clone(),
+            fee: venue.fee.unwrap_or(3000),
+            router: UniswapV2Router::new(venue.address, provider),
+            local_math: venue.local_math,
+            factory: Mutex::new(None),
+            pairs: Mutex::new(HashMap::new()),
+        }),
+        DexKind::UniswapV3 => Arc::new(UniswapV3Dex {
+            name: venue.name.clone(),
+            fee: venue.fee.unwrap_or(3000),
+            quoter: UniswapV3Quoter::new(venue.address, provider),
+        }),
+        DexKind::Algebra => Arc::new(AlgebraDex {
+            name: venue.name.clone(),
+            quoter: AlgebraQuoter::new(venue.address, provider),
+        }),
+    }
+}
+
+pub struct UniswapV2Dex {
+    name: String,
+    fee: u32,
+    router: UniswapV2Router<Client>,
+    /// Quote from pair reserves with `get_amount_out` instead of calling `getAmountsOut`
+    local_math: bool,
+    /// Router's factory, looked up once
+    factory: Mutex<Option<Address>>,
+    /// Pair addresses keyed by sorted token addresses
+    pairs: Mutex<HashMap<(Address, Address), Address>>,
+}
+
+impl UniswapV2Dex {
+    pub async fn get_amounts_out(&self, amount_in: U256, path: Vec<Address>, block: BlockId) -> Result<Vec<U256>> {
+        if self.local_math {
+            return self.get_amounts_out_local(amount_in, &path, block).await;
+        }
+        // call getAmountsOut(amount_in, path)
+        let amounts: Vec<U256> = self.router
+            .get_amounts_out(amount_in, path)
+            .block(block)
+            .call()
+            .await?;
+        Ok(amounts)
+    }
+
+    /// Same result as `getAmountsOut`, computed from one `getReserves` per hop.
+    pub async fn get_amounts_out_local(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<Vec<U256>> {
+        let mut amounts = vec![amount_in];
+        for hop in path.windows(2) {
+            let (reserve_in, reserve_out) = self.pair_reserves(hop[0], hop[1], block).await?;
+            let amount = *amounts.last().unwrap();
+            amounts.push(get_amount_out(amount, reserve_in, reserve_out, self.fee)?);
+        }
+        Ok(amounts)
+    }
+
+    /// Pair address for two tokens via the router's factory (`getPair`), cached.
+    pub async fn pair_address(&self, token_a: Address, token_b: Address) -> Result<Address> {
+        let key = sort_tokens(token_a, token_b);
+        if let Some(pair) = self.pairs.lock().unwrap().get(&key) {
+            return Ok(*pair);
+        }
+
+        let cached = *self.factory.lock().unwrap();
+        let factory = match cached {
+            Some(factory) => factory,
+            None => {
+                let factory = self.router.factory().call().await?;
+                *self.factory.lock().unwrap() = Some(factory);
+                factory
+            }
+        };
+
+        let pair = UniswapV2Factory::new(factory, self.router.client())
+            .get_pair(key.0, key.1)
+            .call()
+            .await?;
+        if pair.is_zero() {
+            return Err(SimError::NoPool(format!("{}: no pair for {:?}/{:?}", self.name, token_a, token_b)).into());
+        }
+        self.pairs.lock().unwrap().insert(key, pair);
+        Ok(pair)
+    }
+
+    /// `(reserve_in, reserve_out)` of the `token_in`/`token_out` pair.
+    pub async fn pair_reserves(&self, token_in: Address, token_out: Address, block: BlockId) -> Result<(U256, U256)> {
+        let pair = self.pair_address(token_in, token_out).await?;
+        let (reserve0, reserve1, _) = UniswapV2Pair::new(pair, self.router.client())
+            .get_reserves()
+            .block(block)
+            .call()
+            .await?;
+        let (reserve0, reserve1) = (U256::from(reserve0), U256::from(reserve1));
+        // token0 is the lower address
+        if token_in < token_out {
+            Ok((reserve0, reserve1))
+        } else {
+            Ok((reserve1, reserve0))
+        }
+    }
+}
+
+#[async_trait]
+impl Dex for UniswapV2Dex {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> DexKind {
+        DexKind::UniswapV2
+    }
+
+    fn fee(&self) -> Option<u32> {
+        Some(self.fee)
+    }
+
+    async fn quote(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<U256> {
+        let amounts = self.get_amounts_out(amount_in, path.to_vec(), block).await?;
+        amounts
+            .last()
+            .copied()
+            .ok_or_else(|| anyhow!("getAmountsOut returned no amounts"))
+    }
+
+    async fn quote_hops(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<Vec<U256>> {
+        self.get_amounts_out(amount_in, path.to_vec(), block).await
+    }
+
+    /// Router mode: one `getAmountsOut`. Local mode: one `getReserves` per hop, once the pairs are known.
+    fn quote_calls(&self, amount_in: U256, path: &[Address]) -> Option<Vec<(Address, Bytes)>> {
+        if !self.local_math {
+            let call = self.router.get_amounts_out(amount_in, path.to_vec());
+            return Some(vec![(self.router.address(), call.calldata()?)]);
+        }
+        let pairs = self.pairs.lock().unwrap();
+        path.windows(2)
+            .map(|hop| {
+                let pair = *pairs.get(&sort_tokens(hop[0], hop[1]))?;
+                let call = UniswapV2Pair::new(pair, self.router.client()).get_reserves();
+                Some((pair, call.calldata()?))
+            })
+            .collect()
+    }
+
+    fn decode_quote(&self, amount_in: U256, path: &[Address], results: &[Bytes]) -> Result<U256> {
+        if !self.local_math {
+            let data = results.first().ok_or_else(|| anyhow!("missing getAmountsOut result"))?;
+            let amounts: Vec<U256> = self.router.decode_output("getAmountsOut", data)?;
+            return amounts.last().copied().ok_or_else(|| anyhow!("getAmountsOut returned no amounts"));
+        }
+        // A short answer would otherwise price only part of the path
+        ensure!(
+            results.len() + 1 == path.len(),
+            "{}: {} getReserves results for a {}-hop path",
+            self.name,
+            results.len(),
+            path.len().saturating_sub(1)
+        );
+        let pair = UniswapV2Pair::new(Address::zero(), self.router.client());
+        let mut amount = amount_in;
+        for (hop, data) in path.windows(2).zip(results) {
+            let (reserve0, reserve1, _): (u128, u128, u32) = pair.decode_output("getReserves", data)?;
+            let (reserve0, reserve1) = (U256::from(reserve0), U256::from(reserve1));
+            let (reserve_in, reserve_out) = if hop[0] < hop[1] { (reserve0, reserve1) } else { (reserve1, reserve0) };
+            amount = get_amount_out(amount, reserve_in, reserve_out, self.fee)?;
+        }
+        Ok(amount)
+    }
+
+    async fn reserves(&self, token_in: Address, token_out: Address, block: BlockId) -> Result<Option<(U256, U256)>> {
+        Ok(Some(self.pair_reserves(token_in, token_out, block).await?))
+    }
+
+    async fn pool(&self, token_a: Address, token_b: Address) -> Result<Option<Address>> {
+        Ok(Some(self.pair_address(token_a, token_b).await?))
+    }
+}
+
+pub struct UniswapV3Dex {
+    name: String,
+    fee: u32,
+    quoter: UniswapV3Quoter<Client>,
+}
+
+#[async_trait]
+impl Dex for UniswapV3Dex {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> DexKind {
+        DexKind::UniswapV3
+    }
+
+    fn fee(&self) -> Option<u32> {
+        Some(self.fee)
+    }
+
+    /// Single hops use `quoteExactInputSingle`; longer paths go through `quoteExactInput` with every hop on `fee`.
+    async fn quote(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<U256> {
+        if path.len() == 2 {
+            let params = QuoteExactInputSingleParams {
+                token_in: path[0],
+                token_out: path[1],
+                amount_in,
+                fee: self.fee,
+                sqrt_price_limit_x96: U256::zero(),
+            };
+            let (amount_out, _, _, _) = self.quoter.quote_exact_input_single(params).block(block).call().await?;
+            Ok(amount_out)
+        } else {
+            let encoded = encode_v3_path(path, self.fee)?;
+            let (amount_out, _, _, _) = self.quoter.quote_exact_input(encoded, amount_in).block(block).call().await?;
+            Ok(amount_out)
+        }
+    }
+
+    fn quote_calls(&self, amount_in: U256, path: &[Address]) -> Option<Vec<(Address, Bytes)>> {
+        let calldata = if path.len() == 2 {
+            self.quoter
+                .quote_exact_input_single(QuoteExactInputSingleParams {
+                    token_in: path[0],
+                    token_out: path[1],
+                    amount_in,
+                    fee: self.fee,
+                    sqrt_price_limit_x96: U256::zero(),
+                })
+                .calldata()?
+        } else {
+            self.quoter
+                .quote_exact_input(encode_v3_path(path, self.fee).ok()?, amount_in)
+                .calldata()?
+        };
+        Some(vec![(self.quoter.address(), calldata)])
+    }
+
+    fn decode_quote(&self, _amount_in: U256, path: &[Address], results: &[Bytes]) -> Result<U256> {
+        let data = results.first().ok_or_else(|| anyhow!("missing quoter result"))?;
+        if path.len() == 2 {
+            let (amount_out, _, _, _): (U256, U256, u32, U256) = self.quoter.decode_output("quoteExactInputSingle", data)?;
+            Ok(amount_out)
+        } else {
+            let (amount_out, _, _, _): (U256, Vec<U256>, Vec<u32>, U256) = self.quoter.decode_output("quoteExactInput", data)?;
+            Ok(amount_out)
+        }
+    }
+}
+
+pub struct AlgebraDex {
+    name: String,
+    quoter: AlgebraQuoter<Client>,
+}
+
+#[async_trait]
+impl Dex for AlgebraDex {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> DexKind {
+        DexKind::Algebra
+    }
+
+    fn fee(&self) -> Option<u32> {
+        None
+    }
+
+    async fn quote(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<U256> {
+        if path.len() == 2 {
+            let (amount_out, _fee) = self.quoter
+                .quote_exact_input_single(path[0], path[1], amount_in, U256::zero())
+                .block(block)
+                .call()
+                .await?;
+            Ok(amount_out)
+        } else {
+            let encoded = encode_algebra_path(path)?;
+            let (amount_out, _fees) = self.quoter.quote_exact_input(encoded, amount_in).block(block).call().await?;
+            Ok(amount_out)
+        }
+    }
+
+    fn quote_calls(&self, amount_in: U256, path: &[Address]) -> Option<Vec<(Address, Bytes)>> {
+        let calldata = if path.len() == 2 {
+            self.quoter
+                .quote_exact_input_single(path[0], path[1], amount_in, U256::zero())
+                .calldata()?
+        } else {
+            self.quoter
+                .quote_exact_input(encode_algebra_path(path).ok()?, amount_in)
+                .calldata()?
+        };
+        Some(vec![(self.quoter.address(), calldata)])
+    }
+
+    fn decode_quote(&self, _amount_in: U256, path: &[Address], results: &[Bytes]) -> Result<U256> {
+        let data = results.first().ok_or_else(|| anyhow!("missing quoter result"))?;
+        if path.len() == 2 {
+            let (amount_out, _fee): (U256, u16) = self.quoter.decode_output("quoteExactInputSingle", data)?;
+            Ok(amount_out)
+        } else {
+            let (amount_out, _fees): (U256, Vec<u16>) = self.quoter.decode_output("quoteExactInput", data)?;
+            Ok(amount_out)
+        }
+    }
+}
+
+/// V3 packed path: token (20) | fee (3) | token (20) | ...
+fn encode_v3_path(path: &[Address], fee: u32) -> Result<Bytes> {
+    if path.len() < 2 {
+        bail!("path needs at least two tokens");
+    }
+    let mut out = Vec::with_capacity(path.len() * 23);
+    for (i, token) in path.iter().enumerate() {
+        if i > 0 {
+            out.extend_from_slice(&fee.to_be_bytes()[1..]);
+        }
+        out.extend_from_slice(token.as_bytes());
+    }
+    Ok(out.into())
+}
+
+/// Algebra packed path: tokens back to back, no fee (it is read from the pool)
+fn encode_algebra_path(path: &[Address]) -> Result<Bytes> {
+    if path.len() < 2 {
+        bail!("path needs at least two tokens");
+    }
+    let out: Vec<u8> = path.iter().flat_map(|t| t.as_bytes().to_vec()).collect();
+    Ok(out.into())
+}
+
+/// UniswapV2Library.getAmountOut with the fee in hundredths of a bip. For fees that are a multiple
+/// of 100 (3000 = 997/1000, 2500 = 9975/10000, ...) this floors to exactly the on-chain result.
+pub fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee: u32) -> Result<U256> {
+    if amount_in.is_zero() {
+        return Err(SimError::Revert("INSUFFICIENT_INPUT_AMOUNT".to_string()).into());
+    }
+    if reserve_in.is_zero() || reserve_out.is_zero() {
+        return Err(SimError::Revert("INSUFFICIENT_LIQUIDITY".to_string()).into());
+    }
+    let amount_in_with_fee = amount_in * U256::from(1_000_000 - fee);
+    let numerator = amount_in_with_fee * reserve_out;
+    let denominator = reserve_in * U256::from(1_000_000) + amount_in_with_fee;
+    Ok(numerator / denominator)
+}
+
+/// UniswapV2Library.sortTokens
+fn sort_tokens(token_a: Address, token_b: Address) -> (Address, Address) {
+    if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// UniswapV2Library.getAmountOut exactly as deployed: `amountIn * num` over `reserveIn * den + amountIn * num`.
+    fn solidity_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, num: u64, den: u64) -> U256 {
+        let amount_in_with_fee = amount_in * U256::from(num);
+        amount_in_with_fee * reserve_out / (reserve_in * U256::from(den) + amount_in_with_fee)
+    }
+
+    fn u(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    #[test]
+    fn matches_router_vectors() {
+        // (amount_in, reserve_in, reserve_out, fee, expected) with expected from the on-chain formula
+        let cases = [
+            ("1000000000", "2500000000000", "1000000000000000000000", 3000, "398641021960442175"),
+            ("1000000000000000000", "1000000000000000000000", "2500000000000", 3000, "2490017452"),
+            (
+                "2596148429267413814265248164610047",
+                "5192296858534827628530496329220095",
+                "5192296858534827628530496329220095",
+                3000,
+                "1727300623276350732614249195940084",
+            ),
+            ("12345678901234567890", "98765432109876543210987", "1234567890123456789012", 2000, "153993132246391066"),
+            ("500000000000000000", "3000000000000000000000", "7000000000", 2500, "1163556"),
+            ("1", "1000", "1000", 3000, "0"),
+        ];
+        for (amount_in, reserve_in, reserve_out, fee, expected) in cases {
+            assert_eq!(get_amount_out(u(amount_in), u(reserve_in), u(reserve_out), fee).unwrap(), u(expected));
+        }
+    }
+
+    #[test]
+    fn matches_solidity_formula_across_fee_tiers() {
+        // (fee in hundredths of a bip, router numerator, router denominator)
+        let tiers = [(3000, 997, 1000), (2000, 998, 1000), (2500, 9975, 10000), (1000, 999, 1000)];
+        let mut seed: u64 = 0x9e3779b97f4a7c15;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..2_000 {
+            let reserve_in = U256::from(next()) * U256::from(next() % 1_000_000 + 1);
+            let reserve_out = U256::from(next()) * U256::from(next() % 1_000_000 + 1);
+            let amount_in = U256::from(next() % 1_000_000_000_000 + 1);
+            for (fee, num, den) in tiers {
+                assert_eq!(
+                    get_amount_out(amount_in, reserve_in, reserve_out, fee).unwrap(),
+                    solidity_amount_out(amount_in, reserve_in, reserve_out, num, den),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn chains_hops_like_get_amounts_out() {
+        let first = get_amount_out(u("1000000000"), u("2500000000000"), u("1000000000000000000000"), 3000).unwrap();
+        let second = get_amount_out(first, u("400000000000000000000"), u("1000000000000000000000000"), 3000).unwrap();
+        assert_eq!(first, u("398641021960442175"));
+        assert_eq!(second, u("992626460931576350429"));
+    }
+
+    #[test]
+    fn rejects_what_the_router_rejects() {
+        assert!(get_amount_out(U256::zero(), u("1000"), u("1000"), 3000).is_err());
+        assert!(get_amount_out(u("1000"), U256::zero(), u("1000"), 3000).is_err());
+        assert!(get_amount_out(u("1000"), u("1000"), U256::zero(), 3000).is_err());
+    }
+
+    #[test]
+    fn orders_tokens_like_the_factory() {
+        let low = Address::from_low_u64_be(1);
+        let high = Address::from_low_u64_be(2);
+        assert_eq!(sort_tokens(high, low), (low, high));
+        assert_eq!(sort_tokens(low, high), (low, high));
+    }
+
+    #[test]
+    fn local_quotes_need_reserves_for_every_hop() {
+        let dex = UniswapV2Dex {
+            name: "Local".to_string(),
+            fee: 3000,
+            router: UniswapV2Router::new(Address::zero(), Arc::new(crate::mock::offline())),
+            local_math: true,
+            factory: Mutex::new(None),
+            pairs: Mutex::new(HashMap::new()),
+        };
+        // One getReserves answer, for the first hop only
+        let results = [Bytes::from(ethers::abi::encode(&[
+            ethers::abi::Token::Uint(u("1000000000000000000000")),
+            ethers::abi::Token::Uint(u("2500000000000")),
+            ethers::abi::Token::Uint(U256::zero()),
+        ]))];
+        let path = [Address::from_low_u64_be(1), Address::from_low_u64_be(2), Address::from_low_u64_be(3)];
+        assert!(dex.decode_quote(u("1000000000000000000"), &path, &results).is_err());
+        assert!(dex.decode_quote(u("1000000000000000000"), &path[..2], &results).is_ok());
+    }
+
+    /// Compares local math against the live router. Needs POLYGON_RPC, DEX1_ROUTER, WETH and USDC;
+    /// run with `cargo test -- --ignored`. Both reads are pinned to the same block.
+    #[tokio::test]
+    #[ignore]
+    async fn matches_live_router() {
+        dotenv::dotenv().ok();
+        let env = |key: &str| std::env::var(key).unwrap_or_else(|_| panic!("{key} must be set"));
+        let provider = Arc::new(crate::rpc::connect(&[env("POLYGON_RPC")], 1, 1, None).unwrap());
+        let block = BlockId::from(provider.get_block_number().await.unwrap());
+        let dex = UniswapV2Dex {
+            name: "live".to_string(),
+            fee: 3000,
+            router: UniswapV2Router::new(env("DEX1_ROUTER").parse::<Address>().unwrap(), provider),
+            local_math: false,
+            factory: Mutex::new(None),
+            pairs: Mutex::new(HashMap::new()),
+        };
+        let usdc: Address = env("USDC").parse().unwrap();
+        let weth: Address = env("WETH").parse().unwrap();
 
-pub async fn get_amounts_out(
-    provider: Arc<Provider<Http>>,
-    router_addr: Address,
-    amount_in: U256,
-    path: Vec<Address>,
-) -> Result<Vec<U256>> {
-    let router = UniswapV2Router::new(router_addr, provider);
-    // call getAmountsOut(amount_in, path)
-    let amounts: Vec<U256> = router
-        .get_amounts_out(amount_in, path)
-        .call()
-        .await?;
-    Ok(amounts)
-}
\ No newline at end of file
+        for amount in ["1000000", "1000000000", "100000000000"] {
+            for path in [vec![usdc, weth], vec![usdc, weth, usdc]] {
+                let router = dex.get_amounts_out(u(amount), path.clone(), block).await.unwrap();
+                let local = dex.get_amounts_out_local(u(amount), &path, block).await.unwrap();
+                assert_eq!(router, local, "amount {amount} path {path:?}");
+            }
+        }
+    }
+}
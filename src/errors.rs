@@ -0,0 +1,265 @@
+use ethers::abi::AbiDecode;
+use ethers::contract::{AbiError, ContractError};
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use thiserror::Error;
+use crate::dex::{Dex, DexKind};
+use crate::rpc::{Client, RpcFailure};
+
+/// A failed simulation call, by what went wrong.
+#[derive(Debug, Clone, Error)]
+pub enum SimError {
+    /// Endpoint unreachable, answering 5xx, or endpoints disagreeing in quorum mode
+    #[error("transport failure: {0}")]
+    Transport(String),
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// The call ran and reverted, e.g. `INSUFFICIENT_LIQUIDITY`
+    #[error("reverted: {0}")]
+    Revert(String),
+    /// The venue has no pool for the pair
+    #[error("no pool: {0}")]
+    NoPool(String),
+    /// Return data did not match the ABI
+    #[error("decode failure: {0}")]
+    Decode(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// How often, and how far apart, a class of failure is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub retries: u32,
+    /// Wait before the first retry; doubled for each further one
+    pub backoff: Duration,
+}
+
+impl SimError {
+    /// Short name the failure is counted under
+    pub fn class(&self) -> &'static str {
+        match self {
+            SimError::Transport(_) => "transport",
+            SimError::RateLimited(_) => "rate_limited",
+            SimError::Timeout(_) => "timeout",
+            SimError::Revert(_) => "revert",
+            SimError::NoPool(_) => "no_pool",
+            SimError::Decode(_) => "decode",
+            SimError::Other(_) => "other",
+        }
+    }
+
+    /// Rate limits and transient network trouble are worth retrying; an answer from the chain
+    /// (revert, missing pool, undecodable data) will be the same next time at the same block.
+    pub fn policy(&self) -> RetryPolicy {
+        let (retries, backoff) = match self {
+            SimError::RateLimited(_) => (3, Duration::from_millis(500)),
+            SimError::Transport(_) => (2, Duration::from_millis(200)),
+            SimError::Timeout(_) => (1, Duration::ZERO),
+            _ => (0, Duration::ZERO),
+        };
+        RetryPolicy { retries, backoff }
+    }
+
+    /// Work out what went wrong from anywhere in an error's chain.
+    pub fn classify(err: &anyhow::Error) -> SimError {
+        for cause in err.chain() {
+            if let Some(e) = cause.downcast_ref::<SimError>() {
+                return e.clone();
+            }
+            if let Some(e) = cause.downcast_ref::<ContractError<Client>>() {
+                return from_contract(e);
+            }
+            if let Some(e) = cause.downcast_ref::<ProviderError>() {
+                return from_provider(e);
+            }
+            if let Some(e) = cause.downcast_ref::<RpcFailure>() {
+                return from_rpc(e);
+            }
+            if cause.is::<AbiError>() || cause.is::<ethers::abi::Error>() {
+                return SimError::Decode(cause.to_string());
+            }
+        }
+        SimError::Other(format!("{err:#}"))
+    }
+}
+
+fn from_contract(e: &ContractError<Client>) -> SimError {
+    match e {
+        ContractError::Revert(data) => SimError::Revert(revert_reason(data)),
+        ContractError::MiddlewareError { e } | ContractError::ProviderError { e } => from_provider(e),
+        ContractError::DecodingError(_) | ContractError::AbiError(_) | ContractError::DetokenizationError(_) => {
+            SimError::Decode(e.to_string())
+        }
+        ContractError::ContractNotDeployed => SimError::NoPool(e.to_string()),
+        ContractError::ConstructorError => SimError::Other(e.to_string()),
+    }
+}
+
+fn from_provider(e: &ProviderError) -> SimError {
+    match e {
+        ProviderError::JsonRpcClientError(inner) => {
+            let inner: &(dyn std::error::Error + 'static) = inner.as_ref();
+            match inner.downcast_ref::<RpcFailure>() {
+                Some(failure) => from_rpc(failure),
+                None => SimError::Other(e.to_string()),
+            }
+        }
+        ProviderError::HTTPError(e) if e.is_timeout() => SimError::Timeout(e.to_string()),
+        ProviderError::HTTPError(e) => SimError::Transport(e.to_string()),
+        ProviderError::SerdeJson(_) | ProviderError::HexError(_) => SimError::Decode(e.to_string()),
+        _ => SimError::Other(e.to_string()),
+    }
+}
+
+fn from_rpc(e: &RpcFailure) -> SimError {
+    match e {
+        RpcFailure::RateLimited { .. } => SimError::RateLimited(e.to_string()),
+        RpcFailure::Timeout { .. } => SimError::Timeout(e.to_string()),
+        RpcFailure::Server { .. } | RpcFailure::Transport { .. } | RpcFailure::Disagreement { .. } => {
+            SimError::Transport(e.to_string())
+        }
+        RpcFailure::Decode { .. } => SimError::Decode(e.to_string()),
+        RpcFailure::Unrecorded { .. } => SimError::Other(e.to_string()),
+        RpcFailure::JsonRpc(error) => match error.as_revert_data() {
+            Some(data) => SimError::Revert(revert_reason(&data)),
+            None if error.message.contains("revert") => SimError::Revert(error.message.clone()),
+            None => SimError::Other(error.to_string()),
+        },
+    }
+}
+
+/// Human readable revert reason: the message of an `Error(string)` revert, otherwise the raw data.
+pub fn revert_reason(data: &Bytes) -> String {
+    if data.is_empty() {
+        return "reverted without data".to_string();
+    }
+    // Error(string) selector
+    if data.len() > 4 && data[..4] == [0x08, 0xc3, 0x79, 0xa0] {
+        if let Ok(reason) = String::decode(&data[4..]) {
+            return reason;
+        }
+    }
+    format!("reverted with {data}")
+}
+
+/// Failures of one class since startup.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ClassCount {
+    /// Calls that still failed after their retries
+    pub failures: u64,
+    /// Extra attempts made
+    pub retries: u64,
+    /// Calls that succeeded on a retry
+    pub recovered: u64,
+}
+
+/// Per-class failure counters, served to the dashboard.
+#[derive(Debug, Default)]
+pub struct ErrorStats {
+    counts: Mutex<BTreeMap<&'static str, ClassCount>>,
+}
+
+impl ErrorStats {
+    pub fn new() -> Arc<Self> {
+        Arc::new(ErrorStats::default())
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, ClassCount> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    fn count(&self, class: &'static str, update: impl FnOnce(&mut ClassCount)) {
+        update(self.counts.lock().unwrap().entry(class).or_default());
+    }
+
+    /// Run `call`, retrying per the policy of each failure's class. The error returned is the typed
+    /// `SimError` of the last attempt.
+    pub async fn retry<T, F, Fut>(&self, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retried: Option<&'static str> = None;
+        let mut attempt = 0;
+        loop {
+            let error = match call().await {
+                Ok(value) => {
+                    if let Some(class) = retried {
+                        self.count(class, |count| count.recovered += 1);
+                    }
+                    return Ok(value);
+                }
+                Err(e) => SimError::classify(&e),
+            };
+            let policy = error.policy();
+            if attempt >= policy.retries {
+                self.count(error.class(), |count| count.failures += 1);
+                return Err(error.into());
+            }
+            self.count(error.class(), |count| count.retries += 1);
+            tokio::time::sleep(policy.backoff * 2u32.pow(attempt)).await;
+            retried = Some(error.class());
+            attempt += 1;
+        }
+    }
+}
+
+/// A venue whose calls are retried per failure class and counted in `ErrorStats`.
+pub struct RetryingDex {
+    inner: Arc<dyn Dex>,
+    stats: Arc<ErrorStats>,
+}
+
+/// Wrap `venue` so its failures are classified, retried and counted.
+pub fn retrying(venue: Arc<dyn Dex>, stats: Arc<ErrorStats>) -> Arc<dyn Dex> {
+    Arc::new(RetryingDex { inner: venue, stats })
+}
+
+#[async_trait]
+impl Dex for RetryingDex {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn kind(&self) -> DexKind {
+        self.inner.kind()
+    }
+
+    fn fee(&self) -> Option<u32> {
+        self.inner.fee()
+    }
+
+    async fn quote(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<U256> {
+        self.stats.retry(|| self.inner.quote(amount_in, path, block)).await
+    }
+
+    fn quote_calls(&self, amount_in: U256, path: &[Address]) -> Option<Vec<(Address, Bytes)>> {
+        self.inner.quote_calls(amount_in, path)
+    }
+
+    fn decode_quote(&self, amount_in: U256, path: &[Address], results: &[Bytes]) -> Result<U256> {
+        self.inner.decode_quote(amount_in, path, results)
+    }
+
+    async fn reserves(&self, token_in: Address, token_out: Address, block: BlockId) -> Result<Option<(U256, U256)>> {
+        self.stats.retry(|| self.inner.reserves(token_in, token_out, block)).await
+    }
+
+    async fn pool(&self, token_a: Address, token_b: Address) -> Result<Option<Address>> {
+        self.stats.retry(|| self.inner.pool(token_a, token_b)).await
+    }
+
+    async fn quote_hops(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<Vec<U256>> {
+        self.stats.retry(|| self.inner.quote_hops(amount_in, path, block)).await
+    }
+}
@@ -0,0 +1,392 @@
+use ethers::contract::parse_log;
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+use std::time::SystemTime;
+use anyhow::{anyhow, bail, Result};
+use csv::Writer;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use crate::arbitrage::Opportunity;
+use crate::config::{Config, ExecutionConfig};
+use crate::dex::DexKind;
+use crate::rpc::Client;
+use crate::sinks::{append_csv, Sink};
+use crate::store::Store;
+use crate::token::{from_base_units, to_base_units, TokenRegistry};
+
+abigen!(
+    ArbitrageContract,
+    r#"[
+        function execute(address[] routers, address[] path, uint256 amountIn, uint256 minProfit) external returns (uint256 profit)
+        event Executed(address indexed token, uint256 amountIn, uint256 profit)
+    ]"#
+);
+
+pub type SigningClient = SignerMiddleware<Arc<Client>, LocalWallet>;
+
+/// How an execution ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Mined and cleared the contract's min-profit check
+    Included,
+    /// Mined but reverted, e.g. the pools moved between the simulation and inclusion
+    Reverted,
+    /// Not sent: the route cannot be executed or the simulation reverted, so no gas was spent
+    Rejected,
+    /// Signing, sending or waiting for the receipt failed
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Included => "included",
+            Status::Reverted => "reverted",
+            Status::Rejected => "rejected",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+/// One attempt to execute an opportunity, whatever its outcome.
+#[derive(Debug, Clone, Serialize)]
+pub struct Execution {
+    pub opportunity_id: String,
+    /// UNIX seconds when the attempt finished
+    pub timestamp: u64,
+    pub pair: String,
+    pub direction: String,
+    pub quote_symbol: String,
+    /// Net profit the opportunity was detected with
+    pub expected_profit: f64,
+    /// Quote tokens the contract had to gain for the transaction not to revert
+    pub min_profit: Option<f64>,
+    pub status: Status,
+    pub tx_hash: Option<String>,
+    pub nonce: Option<u64>,
+    /// Block the transaction was mined in
+    pub block_number: Option<u64>,
+    pub gas_used: Option<u64>,
+    /// Native token paid for gas
+    pub gas_cost: Option<f64>,
+    /// Quote tokens the contract gained, before gas
+    pub realised_profit: Option<f64>,
+    pub error: Option<String>,
+}
+
+impl Execution {
+    fn new(opportunity: &Opportunity) -> Execution {
+        Execution {
+            opportunity_id: opportunity.id.clone(),
+            timestamp: 0,
+            pair: opportunity.pair.clone(),
+            direction: opportunity.direction.clone(),
+            quote_symbol: opportunity.quote_symbol.clone(),
+            expected_profit: opportunity.net_profit,
+            min_profit: None,
+            status: Status::Rejected,
+            tx_hash: None,
+            nonce: None,
+            block_number: None,
+            gas_used: None,
+            gas_cost: None,
+            realised_profit: None,
+            error: None,
+        }
+    }
+}
+
+/// What the contract is asked to do for one opportunity, in base units.
+struct Plan {
+    routers: Vec<Address>,
+    path: Vec<Address>,
+    amount_in: U256,
+    min_profit: U256,
+    quote_decimals: u8,
+}
+
+/// Signs and sends opportunities to the arbitrage contract, one at a time, tracking the signer's nonce.
+pub struct Executor {
+    client: Arc<SigningClient>,
+    contract: ArbitrageContract<SigningClient>,
+    /// V2 venue name -> router
+    routers: HashMap<String, Address>,
+    /// On-chain symbol of every configured token -> (address, decimals)
+    tokens: HashMap<String, (Address, u8)>,
+    /// Next nonce to use; `None` re-reads it from the chain, e.g. after a send failed
+    nonce: tokio::sync::Mutex<Option<U256>>,
+    slippage_bps: u32,
+}
+
+impl Executor {
+    /// Executor signing with `execution.wallet` on the provider's chain. Opportunities are matched to
+    /// token addresses by the symbols the tokens report on-chain.
+    pub async fn connect(cfg: &Config, execution: &ExecutionConfig, provider: Arc<Client>, tokens: &TokenRegistry) -> Result<Executor> {
+        let chain_id = provider.get_chainid().await?.as_u64();
+        let wallet = execution.wallet.clone().with_chain_id(chain_id);
+        let client = Arc::new(SignerMiddleware::new(provider, wallet));
+
+        let mut symbols = HashMap::new();
+        for address in cfg.tokens.values() {
+            let info = tokens.get(*address).await?;
+            if let Some((other, _)) = symbols.insert(info.symbol.clone(), (*address, info.decimals)) {
+                bail!("tokens {other:?} and {address:?} both report symbol {}", info.symbol);
+            }
+        }
+        let routers = cfg
+            .venues
+            .iter()
+            .filter(|venue| venue.kind == DexKind::UniswapV2)
+            .map(|venue| (venue.name.clone(), venue.address))
+            .collect();
+
+        Ok(Executor {
+            contract: ArbitrageContract::new(execution.contract, client.clone()),
+            client,
+            routers,
+            tokens: symbols,
+            nonce: tokio::sync::Mutex::new(None),
+            slippage_bps: cfg.slippage_bps,
+        })
+    }
+
+    pub fn address(&self) -> Address {
+        self.client.address()
+    }
+
+    /// Simulate, sign and send `opportunity`, then wait for its receipt. Failures are reported in the
+    /// returned execution rather than as errors.
+    pub async fn execute(&self, opportunity: &Opportunity) -> Execution {
+        let mut execution = Execution::new(opportunity);
+        if let Err(e) = self.send(opportunity, &mut execution).await {
+            execution.error = Some(format!("{e:#}"));
+        }
+        execution.timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        execution
+    }
+
+    async fn send(&self, opportunity: &Opportunity, execution: &mut Execution) -> Result<()> {
+        let plan = self.plan(opportunity)?;
+        execution.min_profit = from_base_units(plan.min_profit, plan.quote_decimals)?.to_f64();
+        let call = self.contract.execute(plan.routers, plan.path, plan.amount_in, plan.min_profit);
+
+        // A revert here costs nothing: the pools moved or the contract cannot run the route
+        call.call().await.map_err(|e| anyhow!("simulation reverted: {}", revert_reason(&e)))?;
+
+        // Held until the receipt arrives so transactions go out one at a time with consecutive nonces
+        let mut next_nonce = self.nonce.lock().await;
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => self.client.get_transaction_count(self.address(), Some(BlockNumber::Pending.into())).await?,
+        };
+        let mut tx = call.tx.clone();
+        tx.set_nonce(nonce);
+        execution.status = Status::Failed;
+        execution.nonce = Some(nonce.as_u64());
+
+        let pending = match self.client.send_transaction(tx, None).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                // The nonce may or may not have been used; ask the chain next time
+                *next_nonce = None;
+                return Err(e.into());
+            }
+        };
+        *next_nonce = Some(nonce + 1);
+        execution.tx_hash = Some(format!("{:?}", pending.tx_hash()));
+
+        let receipt = pending.await?.ok_or_else(|| anyhow!("transaction dropped from the mempool"))?;
+        execution.block_number = receipt.block_number.map(|number| number.as_u64());
+        execution.gas_used = receipt.gas_used.map(|gas| gas.as_u64());
+        if let (Some(gas_used), Some(price)) = (receipt.gas_used, receipt.effective_gas_price) {
+            execution.gas_cost = from_base_units(gas_used * price, 18)?.to_f64();
+        }
+        if receipt.status != Some(1.into()) {
+            execution.status = Status::Reverted;
+            bail!("transaction reverted on-chain");
+        }
+        execution.status = Status::Included;
+        let executed = receipt
+            .logs
+            .into_iter()
+            .filter(|log| log.address == self.contract.address())
+            .find_map(|log| parse_log::<ExecutedFilter>(log).ok())
+            .ok_or_else(|| anyhow!("no Executed event in the receipt"))?;
+        execution.realised_profit = from_base_units(executed.profit, plan.quote_decimals)?.to_f64();
+        Ok(())
+    }
+
+    /// Routers, token path and amounts for an opportunity. The contract must end with at least the
+    /// final output at `SLIPPAGE_BPS` of slippage, i.e. the slippage profit plus the gas it was charged.
+    fn plan(&self, o: &Opportunity) -> Result<Plan> {
+        let token = |symbol: &str| {
+            self.tokens
+                .get(symbol)
+                .copied()
+                .ok_or_else(|| anyhow!("token {symbol} is not in TOKENS"))
+        };
+        let routers = o
+            .legs
+            .iter()
+            .map(|leg| {
+                self.routers
+                    .get(&leg.venue)
+                    .copied()
+                    .ok_or_else(|| anyhow!("{} is not a V2 venue; only V2 routers can be executed", leg.venue))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let path = o.path.iter().map(|symbol| Ok(token(symbol)?.0)).collect::<Result<Vec<_>>>()?;
+        let (_, decimals) = token(&o.quote_symbol)?;
+
+        // The same exact amounts the opportunity was priced from, not its f64 copies
+        let (amount_in, amount_out) = o.exact.ok_or_else(|| anyhow!("{} has no exact amounts to execute", o.id))?;
+        let min_out = amount_out * (Decimal::ONE - Decimal::from(self.slippage_bps) / Decimal::from(10_000));
+        let min_profit = min_out - amount_in;
+        if min_profit.is_sign_negative() {
+            bail!("{} loses {} {} at the slippage tolerance", o.id, -min_profit, o.quote_symbol);
+        }
+        Ok(Plan {
+            routers,
+            path,
+            amount_in: to_base_units(amount_in, decimals)?,
+            min_profit: to_base_units(min_profit, decimals)?,
+            quote_decimals: decimals,
+        })
+    }
+}
+
+/// The revert message of a failed call, or the error itself
+fn revert_reason(error: &ContractError<SigningClient>) -> String {
+    error
+        .decode_revert::<String>()
+        .unwrap_or_else(|| error.to_string())
+}
+
+/// Columns of the executions CSV log, in order
+const EXECUTION_HEADER: [&str; 14] = [
+    "timestamp",
+    "opportunity_id",
+    "pair",
+    "direction",
+    "quote",
+    "expected_profit",
+    "min_profit",
+    "status",
+    "tx_hash",
+    "nonce",
+    "block",
+    "gas_used",
+    "gas_cost",
+    "realised_profit",
+];
+
+/// Appends one row per execution attempt to a CSV file.
+pub struct ExecutionLog {
+    writer: Writer<File>,
+}
+
+impl ExecutionLog {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(ExecutionLog { writer: append_csv(path, &EXECUTION_HEADER)? })
+    }
+
+    pub fn record(&mut self, e: &Execution) -> Result<()> {
+        fn optional<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map(T::to_string).unwrap_or_default()
+        }
+        self.writer.write_record([
+            e.timestamp.to_string(),
+            e.opportunity_id.clone(),
+            e.pair.clone(),
+            e.direction.clone(),
+            e.quote_symbol.clone(),
+            e.expected_profit.to_string(),
+            optional(&e.min_profit),
+            e.status.as_str().to_string(),
+            optional(&e.tx_hash),
+            optional(&e.nonce),
+            optional(&e.block_number),
+            optional(&e.gas_used),
+            optional(&e.gas_cost),
+            optional(&e.realised_profit),
+        ])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Hands robust opportunities to the executor task.
+pub struct ExecutionSink {
+    queue: mpsc::UnboundedSender<Opportunity>,
+}
+
+impl Sink for ExecutionSink {
+    fn record(&mut self, opportunity: &Opportunity) -> Result<()> {
+        if opportunity.robust {
+            self.queue.send(opportunity.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// Start executing recorded opportunities in the background and return the sink that feeds it.
+/// Opportunities from a block older than the newest one queued are skipped as stale; every attempt
+/// goes to `log` and the store.
+pub fn spawn(executor: Executor, mut log: Option<ExecutionLog>, store: Option<Arc<Store>>) -> ExecutionSink {
+    let (queue, mut opportunities) = mpsc::unbounded_channel::<Opportunity>();
+    tokio::spawn(async move {
+        while let Some(first) = opportunities.recv().await {
+            let mut batch = vec![first];
+            while let Ok(next) = opportunities.try_recv() {
+                batch.push(next);
+            }
+            let newest = batch.iter().map(|o| o.block_number).max().unwrap_or_default();
+            for opportunity in batch {
+                if opportunity.block_number < newest {
+                    println!("⏭ Skipping stale opportunity {} (block {newest} is out)", opportunity.id);
+                    continue;
+                }
+                let execution = executor.execute(&opportunity).await;
+                match execution.status {
+                    Status::Included => println!(
+                        "💸 Executed {} tx={} gas={} realised={} {} (expected {:.4})",
+                        execution.opportunity_id,
+                        execution.tx_hash.as_deref().unwrap_or_default(),
+                        execution.gas_used.unwrap_or_default(),
+                        execution.realised_profit.unwrap_or_default(),
+                        execution.quote_symbol,
+                        execution.expected_profit
+                    ),
+                    status => eprintln!(
+                        "Execution of {} {}: {}",
+                        execution.opportunity_id,
+                        status.as_str(),
+                        execution.error.as_deref().unwrap_or_default()
+                    ),
+                }
+                if let Some(log) = &mut log {
+                    if let Err(e) = log.record(&execution) {
+                        eprintln!("Failed to log execution of {}: {:?}", execution.opportunity_id, e);
+                    }
+                }
+                if let Some(store) = store.clone() {
+                    // SQLite blocks; keep it off the runtime's worker threads
+                    let row = execution.clone();
+                    let stored = tokio::task::spawn_blocking(move || store.insert_execution(&row)).await;
+                    if let Err(e) = stored.map_err(anyhow::Error::from).and_then(|result| result) {
+                        eprintln!("Failed to store execution of {}: {:?}", execution.opportunity_id, e);
+                    }
+                }
+            }
+        }
+    });
+    ExecutionSink { queue }
+}
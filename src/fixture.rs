@@ -0,0 +1,354 @@
+use ethers::abi::{AbiDecode, AbiEncode};
+use ethers::prelude::*;
+use ethers::providers::JsonRpcError;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::multicall::{Aggregate3Call, Aggregate3Return, Call3};
+use crate::rpc::RpcFailure;
+
+/// One recorded request and its answer: a line of a fixture file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    method: String,
+    params: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    /// The node's error, e.g. a revert; requests that failed for endpoint reasons are not recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RecordedError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedError {
+    code: i64,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl From<&JsonRpcError> for RecordedError {
+    fn from(error: &JsonRpcError) -> Self {
+        RecordedError { code: error.code, message: error.message.clone(), data: error.data.clone() }
+    }
+}
+
+impl From<RecordedError> for JsonRpcError {
+    fn from(error: RecordedError) -> Self {
+        JsonRpcError { code: error.code, message: error.message, data: error.data }
+    }
+}
+
+/// What a sub-call of a failed `aggregate3` entry would have answered as a plain `eth_call`
+fn revert(data: &Bytes) -> RecordedError {
+    RecordedError { code: 3, message: "execution reverted".to_string(), data: Some(json!(data)) }
+}
+
+type Answer = Result<Value, RecordedError>;
+
+/// Requests are matched on method and parameters; `eth_call`s only on target, calldata and block,
+/// so calls sent through ethers' contract bindings and ones split out of a multicall line up.
+fn key(method: &str, params: &Value) -> String {
+    match method {
+        "eth_call" => {
+            let tx = &params[0];
+            let field = |name: &str| tx[name].as_str().unwrap_or_default().to_lowercase();
+            let data = if tx["data"].is_null() { field("input") } else { field("data") };
+            format!("eth_call {} {} {}", field("to"), data, params.get(1).unwrap_or(&json!("latest")))
+        }
+        _ => format!("{method} {params}"),
+    }
+}
+
+/// The calls of an `eth_call` to Multicall3 `aggregate3`, with the block they are pinned to
+fn aggregate(method: &str, params: &Value) -> Option<(Vec<Call3>, Value)> {
+    if method != "eth_call" {
+        return None;
+    }
+    let tx = &params[0];
+    let data = tx.get("data").or_else(|| tx.get("input"))?;
+    let data: Bytes = serde_json::from_value(data.clone()).ok()?;
+    let call = Aggregate3Call::decode(&data).ok()?;
+    Some((call.calls, params.get(1).cloned().unwrap_or(json!("latest"))))
+}
+
+/// Plain `eth_call` parameters for one sub-call of an `aggregate3`
+fn sub_call(call: &Call3, block: &Value) -> Value {
+    json!([{ "to": call.target, "data": call.call_data }, block])
+}
+
+/// Appends every answer the endpoints give to a fixture file, one JSON object per line. Multicall
+/// `aggregate3` calls are split into their sub-calls, so a replay does not depend on how the calls
+/// happened to be batched.
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path).with_context(|| format!("opening {path}"))?;
+        Ok(Recorder { file: Mutex::new(LineWriter::new(file)) })
+    }
+
+    pub fn record(&self, method: &str, params: &Value, answer: &Result<Value, RpcFailure>) {
+        let entries = match answer {
+            Ok(result) => match aggregate(method, params) {
+                Some((calls, block)) => match split(&calls, &block, result) {
+                    Some(entries) => entries,
+                    None => return eprintln!("Could not split a multicall answer for the fixture"),
+                },
+                None => vec![Entry { method: method.to_string(), params: params.clone(), result: Some(result.clone()), error: None }],
+            },
+            Err(RpcFailure::JsonRpc(error)) => {
+                vec![Entry { method: method.to_string(), params: params.clone(), result: None, error: Some(error.into()) }]
+            }
+            // Endpoint trouble says nothing about the chain
+            Err(_) => return,
+        };
+        let mut file = self.file.lock().unwrap();
+        for entry in entries {
+            // Plain JSON values always serialise
+            let line = serde_json::to_string(&entry).expect("fixture entry serialises");
+            if let Err(e) = writeln!(file, "{line}") {
+                eprintln!("Failed to record {method} to the fixture: {:?}", e);
+            }
+        }
+    }
+}
+
+/// One plain `eth_call` entry per sub-call of an answered `aggregate3`
+fn split(calls: &[Call3], block: &Value, result: &Value) -> Option<Vec<Entry>> {
+    let data: Bytes = serde_json::from_value(result.clone()).ok()?;
+    let results = Aggregate3Return::decode(&data).ok()?.return_data;
+    (results.len() == calls.len()).then(|| {
+        calls
+            .iter()
+            .zip(results)
+            .map(|(call, (success, return_data))| Entry {
+                method: "eth_call".to_string(),
+                params: sub_call(call, block),
+                result: success.then(|| json!(return_data)),
+                error: (!success).then(|| revert(&return_data)),
+            })
+            .collect()
+    })
+}
+
+/// Serves answers recorded by a `Recorder` instead of asking an endpoint. A request asked several
+/// times (e.g. the latest block) gets its recorded answers in order, then the last one again.
+/// The default replays an empty fixture.
+#[derive(Debug, Default)]
+pub struct Replay {
+    answers: Mutex<HashMap<String, VecDeque<Answer>>>,
+    requests: AtomicU64,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {path}"))?;
+        let mut answers: HashMap<String, VecDeque<Answer>> = HashMap::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line).with_context(|| format!("{path} line {}", number + 1))?;
+            let answer = match entry.error {
+                Some(error) => Err(error),
+                None => Ok(entry.result.unwrap_or(Value::Null)),
+            };
+            answers.entry(key(&entry.method, &entry.params)).or_default().push_back(answer);
+        }
+        Ok(Replay { answers: Mutex::new(answers), requests: AtomicU64::new(0) })
+    }
+
+    /// Requests served since startup
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn answer(&self, method: &str, params: &Value) -> Result<Value, RpcFailure> {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let Some((calls, block)) = aggregate(method, params) else {
+            return self.next(method, params)?.map_err(|error| RpcFailure::JsonRpc(error.into()));
+        };
+        // Rebuild the batch from its sub-calls, whichever batches they were recorded in
+        let mut results = Vec::with_capacity(calls.len());
+        for call in &calls {
+            results.push(match self.next(method, &sub_call(call, &block))? {
+                Ok(data) => (true, serde_json::from_value(data).unwrap_or_default()),
+                Err(error) => (false, JsonRpcError::from(error).as_revert_data().unwrap_or_default()),
+            });
+        }
+        Ok(json!(Bytes::from(Aggregate3Return { return_data: results }.encode())))
+    }
+
+    fn next(&self, method: &str, params: &Value) -> Result<Answer, RpcFailure> {
+        let mut answers = self.answers.lock().unwrap();
+        let queue = answers
+            .get_mut(&key(method, params))
+            .ok_or_else(|| RpcFailure::Unrecorded { method: method.to_string() })?;
+        Ok(match queue.len() {
+            1 => queue[0].clone(),
+            _ => queue.pop_front().expect("recorded answers are never empty"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::transaction::eip2718::TypedTransaction;
+    use crate::rpc::{Client, Transport};
+
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("arb-fixture-{}-{name}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn address(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    fn multicall(calls: &[Call3]) -> Value {
+        let data = Bytes::from(Aggregate3Call { calls: calls.to_vec() }.encode());
+        json!([{ "to": address(0xca), "data": data }, "0x64"])
+    }
+
+    fn call(target: u8, data: &[u8]) -> Call3 {
+        Call3 { target: address(target), allow_failure: true, call_data: Bytes::from(data.to_vec()) }
+    }
+
+    fn plain(target: u8, data: &[u8]) -> TypedTransaction {
+        TransactionRequest::new().to(address(target)).data(data.to_vec()).into()
+    }
+
+    #[test]
+    fn repeated_requests_get_their_answers_in_order_then_the_last_again() {
+        let path = path("order");
+        let recorder = Recorder::create(&path).unwrap();
+        let params = json!([]);
+        recorder.record("eth_blockNumber", &params, &Ok(json!("0x1")));
+        recorder.record("eth_blockNumber", &params, &Ok(json!("0x2")));
+        drop(recorder);
+
+        let replay = Replay::load(&path).unwrap();
+        let answers: Vec<Value> = (0..3).map(|_| replay.answer("eth_blockNumber", &params).unwrap()).collect();
+        assert_eq!(answers, vec![json!("0x1"), json!("0x2"), json!("0x2")]);
+        assert_eq!(replay.requests(), 3);
+    }
+
+    #[test]
+    fn node_errors_are_replayed_and_endpoint_faults_are_not_recorded() {
+        let path = path("errors");
+        let recorder = Recorder::create(&path).unwrap();
+        let reverted = json!([{ "to": address(1), "data": "0x01" }, "0x64"]);
+        let error = JsonRpcError { code: 3, message: "execution reverted".to_string(), data: None };
+        recorder.record("eth_call", &reverted, &Err(RpcFailure::JsonRpc(error)));
+        recorder.record("eth_gasPrice", &json!([]), &Err(RpcFailure::RateLimited { endpoint: "rpc".to_string() }));
+        drop(recorder);
+
+        let replay = Replay::load(&path).unwrap();
+        match replay.answer("eth_call", &reverted) {
+            Err(RpcFailure::JsonRpc(error)) => assert_eq!(error.message, "execution reverted"),
+            other => panic!("expected the recorded revert, got {other:?}"),
+        }
+        assert!(matches!(replay.answer("eth_gasPrice", &json!([])), Err(RpcFailure::Unrecorded { .. })));
+    }
+
+    #[tokio::test]
+    async fn multicall_answers_are_replayed_however_the_calls_are_batched() {
+        let path = path("multicall");
+        let recorder = Recorder::create(&path).unwrap();
+        let calls = [call(1, &[0xaa]), call(2, &[0xbb])];
+        let reason = Bytes::from(vec![0xde, 0xad]);
+        let answer = Aggregate3Return { return_data: vec![(true, Bytes::from(vec![0x11])), (false, reason.clone())] };
+        recorder.record("eth_call", &multicall(&calls), &Ok(json!(Bytes::from(answer.encode()))));
+        drop(recorder);
+
+        // The same calls in another batch, in another order
+        let replay = Replay::load(&path).unwrap();
+        let rebatched = replay.answer("eth_call", &multicall(&[calls[1].clone(), calls[0].clone()])).unwrap();
+        let rebatched: Bytes = serde_json::from_value(rebatched).unwrap();
+        assert_eq!(
+            Aggregate3Return::decode(&rebatched).unwrap().return_data,
+            vec![(false, reason), (true, Bytes::from(vec![0x11]))]
+        );
+
+        // And one of them as a plain eth_call through the provider
+        let provider: Client = Provider::new(Transport::Replay(replay));
+        let block = Some(BlockId::from(100u64));
+        assert_eq!(provider.call(&plain(1, &[0xaa]), block).await.unwrap(), Bytes::from(vec![0x11]));
+        assert!(provider.call(&plain(2, &[0xbb]), block).await.is_err());
+        assert!(provider.call(&plain(1, &[0xaa]), Some(BlockId::from(101u64))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_replayed_provider_needs_no_network() {
+        let path = path("provider");
+        let recorder = Recorder::create(&path).unwrap();
+        // ethers sends no parameters as `null`
+        recorder.record("eth_chainId", &Value::Null, &Ok(json!("0x89")));
+        drop(recorder);
+
+        let provider = crate::rpc::replay(&path).unwrap();
+        assert_eq!(provider.get_chainid().await.unwrap(), U256::from(137));
+        assert_eq!(provider.as_ref().requests(), 1);
+    }
+
+    /// A run recorded against two V2 venues whose WETH/USDC pools hold 1000 WETH at 2000 (`Cheap`)
+    /// and 2100 (`Dear`) USDC, with plain `eth_call`s and the default slippage
+    const SKEWED_POOLS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/skewed_pools.jsonl");
+
+    #[tokio::test]
+    async fn a_recorded_evaluation_replays_offline() {
+        let cfg = crate::config::Config::from_values(&[
+            ("REPLAY_FIXTURE", SKEWED_POOLS.to_string()),
+            ("VENUES", "Cheap:v2:0xCf7Ed3AccA5a467e9e704C703E8D87F634fB0Fc9,Dear:v2:0x5FC8d32690cc91D4c39d9d3abcBD16989F875707".to_string()),
+            ("TOKENS", "WETH:0x5FbDB2315678afecb367f032d93F642f64180aa3,USDC:0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512".to_string()),
+            ("PAIRS", "WETH/USDC:1000:5".to_string()),
+            ("WMATIC", "0x5FbDB2315678afecb367f032d93F642f64180aa3".to_string()),
+            ("MULTICALL", "false".to_string()),
+        ]);
+        let provider = std::sync::Arc::new(crate::rpc::replay(cfg.replay_fixture.as_deref().unwrap()).unwrap());
+        let venues = crate::connect_venues(&cfg, &provider, &crate::errors::ErrorStats::new());
+        let tokens = crate::token::TokenRegistry::new(provider.clone());
+        let head = crate::blocks::Heads::poll(provider.clone(), std::time::Duration::ZERO).next().await.unwrap();
+        let gas = crate::gas_costs(&cfg, &provider, &venues, &tokens, head).await;
+
+        let collected = crate::mock::Collected::default();
+        let dashboard = crate::web_monitor::SharedOpportunities::default();
+        let mut sinks: Vec<Box<dyn crate::sinks::Sink>> = vec![
+            Box::new(collected.clone()),
+            Box::new(crate::sinks::DashboardSink::new(dashboard.clone(), Default::default())),
+        ];
+        assert_eq!(crate::evaluate(&cfg, &venues, &tokens, &gas, head, &mut sinks).await.unwrap(), 1);
+
+        let opportunities = collected.opportunities.lock().unwrap();
+        assert_eq!(opportunities.len(), 1, "{opportunities:?}");
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.id, "42:WETH/USDC:Cheap->Dear");
+        assert_eq!(opportunity.path, vec!["USDC", "WETH", "USDC"]);
+        assert_eq!(opportunity.amount_out, 1042.671465);
+        assert_eq!(opportunity.gross_profit, 42.671465);
+        // Gas was priced from the recorded fee history, not the fallback
+        assert!((opportunity.net_profit - 41.35673578508).abs() < 1e-9, "{}", opportunity.net_profit);
+        assert!(opportunity.robust);
+        // And the dashboard serves the same record
+        assert_eq!(dashboard.lock().unwrap().iter().map(|o| o.id.as_str()).collect::<Vec<_>>(), [opportunity.id.as_str()]);
+
+        let evaluations = collected.evaluations.lock().unwrap();
+        assert_eq!(evaluations.len(), 1);
+        let directions: Vec<&str> = evaluations[0].quotes.iter().map(|quote| quote.direction.as_str()).collect();
+        assert_eq!(directions, vec!["Cheap->Dear", "Dear->Cheap"]);
+        assert!(evaluations[0].profitable());
+        assert!(evaluations[0].quotes[1].net_profit < 0.0);
+    }
+}
@@ -0,0 +1,140 @@
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use crate::blocks::Head;
+use crate::config::GasConfig;
+use crate::dex::{Dex, DexKind};
+use crate::token::{self, TokenRegistry};
+use crate::rpc::Client;
+
+/// Intrinsic 21k plus calldata and the executor's own dispatch
+const TX_OVERHEAD_GAS: u64 = 40_000;
+/// Gas per swap hop by venue type, measured on typical Polygon pools
+const V2_SWAP_GAS: u64 = 90_000;
+const V3_SWAP_GAS: u64 = 130_000;
+const ALGEBRA_SWAP_GAS: u64 = 140_000;
+/// Borrowing the input and repaying it in the same transaction
+const FLASH_LOAN_GAS: u64 = 120_000;
+/// Blocks of `eth_feeHistory` the priority fee is taken from
+const FEE_HISTORY_BLOCKS: u64 = 10;
+/// Percentile of each block's priority fees to pay
+const PRIORITY_PERCENTILE: f64 = 50.0;
+
+/// Gas units for one transaction swapping through venues of `kinds` in order, optionally inside a
+/// flash-loan wrapper.
+pub fn route_gas(kinds: &[DexKind], flash_loan: bool) -> u64 {
+    let swaps: u64 = kinds
+        .iter()
+        .map(|kind| match kind {
+            DexKind::UniswapV2 => V2_SWAP_GAS,
+            DexKind::UniswapV3 => V3_SWAP_GAS,
+            DexKind::Algebra => ALGEBRA_SWAP_GAS,
+        })
+        .sum();
+    TX_OVERHEAD_GAS + swaps + if flash_loan { FLASH_LOAN_GAS } else { 0 }
+}
+
+/// Gas pricing for one block: what a unit of gas costs in the native token and what the native
+/// token is worth in each quote token profits are measured in.
+#[derive(Debug, Clone)]
+pub struct GasCosts {
+    /// Next block's base fee plus the median recent priority fee, in wei
+    pub wei_per_gas: U256,
+    /// Price of one native token (MATIC/POL) in each quote token
+    pub native_prices: HashMap<Address, Decimal>,
+    pub flash_loan: bool,
+    /// Flat cost in the quote token for quote tokens that could not be priced
+    pub fallback: Decimal,
+}
+
+impl GasCosts {
+    /// The flat fallback for every route, used when gas cannot be priced at all
+    pub fn fixed(cfg: &GasConfig) -> Self {
+        GasCosts {
+            wei_per_gas: U256::zero(),
+            native_prices: HashMap::new(),
+            flash_loan: cfg.flash_loan,
+            fallback: fallback(cfg),
+        }
+    }
+
+    /// Cost in `quote` of a route swapping through venues of `kinds`.
+    pub fn cost(&self, quote: Address, kinds: &[DexKind]) -> Decimal {
+        let Some(price) = self.native_prices.get(&quote) else {
+            return self.fallback;
+        };
+        let wei = U256::from(route_gas(kinds, self.flash_loan)) * self.wei_per_gas;
+        token::from_base_units(wei, 18).map_or(self.fallback, |native| native * price)
+    }
+
+    /// Gas price in gwei, for logs
+    pub fn gwei(&self) -> Decimal {
+        token::from_base_units(self.wei_per_gas, 9).unwrap_or_default()
+    }
+}
+
+/// Price gas at `head`: EIP-1559 fees from `eth_feeHistory`, and the native token in every token of
+/// `quotes` by quoting one wrapped native token on the configured price venue. Quote tokens that
+/// cannot be priced are logged and charged the flat fallback.
+pub async fn estimate(
+    provider: &Client,
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    cfg: &GasConfig,
+    quotes: &[Address],
+    head: Head,
+) -> Result<GasCosts> {
+    let venue = match &cfg.price_venue {
+        Some(name) => venues
+            .iter()
+            .find(|venue| venue.name() == name)
+            .ok_or_else(|| anyhow!("gas price venue {name} is not configured"))?,
+        None => venues.first().ok_or_else(|| anyhow!("no venues to price gas on"))?,
+    };
+
+    // Fee history and every native price quote are independent
+    let one = U256::exp10(18);
+    let priced: Vec<Address> = quotes.iter().copied().filter(|&quote| quote != cfg.wrapped_native).collect();
+    let paths: Vec<[Address; 2]> = priced.iter().map(|&quote| [cfg.wrapped_native, quote]).collect();
+    let (history, prices) = tokio::join!(
+        provider.fee_history(FEE_HISTORY_BLOCKS, BlockNumber::Number(head.number), &[PRIORITY_PERCENTILE]),
+        join_all(paths.iter().map(|path| venue.quote(one, path, head.id()))),
+    );
+    let history = history?;
+    // The last entry is the base fee of the block after `head`, the one a transaction would land in
+    let base_fee = *history
+        .base_fee_per_gas
+        .last()
+        .ok_or_else(|| anyhow!("fee history returned no base fees"))?;
+    let mut tips: Vec<U256> = history.reward.iter().filter_map(|rewards| rewards.first().copied()).collect();
+    tips.sort();
+    let priority_fee = tips.get(tips.len() / 2).copied().unwrap_or_default();
+
+    let mut native_prices = HashMap::new();
+    if quotes.contains(&cfg.wrapped_native) {
+        native_prices.insert(cfg.wrapped_native, Decimal::ONE);
+    }
+    for (quote, price) in priced.into_iter().zip(prices) {
+        match price {
+            Ok(out) => {
+                native_prices.insert(quote, tokens.get(quote).await?.to_human(out)?);
+            }
+            Err(e) => eprintln!("Failed to price gas in {:?} on {}: {:?}", quote, venue.name(), e),
+        }
+    }
+
+    Ok(GasCosts {
+        wei_per_gas: base_fee + priority_fee,
+        native_prices,
+        flash_loan: cfg.flash_loan,
+        fallback: fallback(cfg),
+    })
+}
+
+fn fallback(cfg: &GasConfig) -> Decimal {
+    Decimal::from_f64(cfg.fallback).unwrap_or_else(|| Decimal::from(5))
+}
@@ -0,0 +1,267 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use anyhow::Result;
+use ethers::types::Address;
+use futures::future::join_all;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use crate::blocks::Head;
+use crate::config::{CycleConfig, PairConfig};
+use crate::dex::Dex;
+use crate::token::TokenRegistry;
+
+/// A token the search may start from, with the amount to probe/trade it at and the profit it must clear.
+#[derive(Debug, Clone)]
+pub struct Seed {
+    pub token: Address,
+    pub trade_amount: Decimal,
+    pub min_profit: Decimal,
+}
+
+/// One quoted swap direction on one venue. `weight` is −ln(effective rate), so a cycle whose
+/// weights sum below zero multiplies the starting amount by more than one.
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub venue: Arc<dyn Dex>,
+    pub weight: f64,
+}
+
+/// Token/pool graph for one evaluation: node `i` is `tokens[i]`.
+pub struct Graph {
+    pub tokens: Vec<Address>,
+    pub edges: Vec<Edge>,
+}
+
+/// A profitable-looking cycle for `triangular::simulate_route` to price exactly.
+pub struct Candidate {
+    /// Tokens in order, first == last
+    pub path: Vec<Address>,
+    /// Venue for each leg
+    pub venues: Vec<Arc<dyn Dex>>,
+    /// Sum of edge weights; exp(−weight) is the gross multiplier
+    pub weight: f64,
+    pub trade_amount: Decimal,
+    pub min_profit: Decimal,
+}
+
+/// Both directions of every configured pair and of every hop of every configured cycle.
+pub fn links(pairs: &[PairConfig], cycles: &[CycleConfig]) -> Vec<(Address, Address)> {
+    let hops = pairs
+        .iter()
+        .map(|pair| (pair.quote, pair.base))
+        .chain(cycles.iter().flat_map(|cycle| cycle.path.windows(2).map(|hop| (hop[0], hop[1]))));
+    let mut links = Vec::new();
+    for (a, b) in hops {
+        for link in [(a, b), (b, a)] {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+/// Start tokens: the quote token of every pair and the first token of every cycle, with their
+/// trade size and threshold. The first entry for a token wins.
+pub fn seeds(pairs: &[PairConfig], cycles: &[CycleConfig]) -> Vec<Seed> {
+    let entries = pairs
+        .iter()
+        .map(|pair| (pair.quote, pair.trade_amount, pair.min_profit))
+        .chain(cycles.iter().map(|cycle| (cycle.path[0], cycle.trade_amount, cycle.min_profit)));
+    let mut seeds: Vec<Seed> = Vec::new();
+    for (token, trade_amount, min_profit) in entries {
+        if seeds.iter().any(|seed| seed.token == token) {
+            continue;
+        }
+        seeds.push(Seed {
+            token,
+            trade_amount: Decimal::from_f64(trade_amount).unwrap_or_else(|| Decimal::from(1000)),
+            min_profit: Decimal::from_f64(min_profit).unwrap_or_else(|| Decimal::from(5)),
+        });
+    }
+    seeds
+}
+
+/// Quote every `links` direction (e.g. both sides of every configured pair) on every venue.
+///
+/// Rates are measured at trade size rather than with a dust amount: seed tokens are probed with
+/// their trade amount, and every other token with the amount the first quote into it produced.
+/// Links that no seed can reach are left out. The graph is explored breadth first and every link
+/// out of one level is quoted concurrently. All quotes are pinned to `head`.
+pub async fn build(
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    links: &[(Address, Address)],
+    seeds: &[Seed],
+    head: Head,
+) -> Result<Graph> {
+    let mut graph = Graph { tokens: Vec::new(), edges: Vec::new() };
+    let mut probes: HashMap<Address, Decimal> = HashMap::new();
+    let mut frontier = Vec::new();
+    for seed in seeds {
+        if probes.insert(seed.token, seed.trade_amount).is_none() {
+            frontier.push(seed.token);
+        }
+    }
+
+    while !frontier.is_empty() {
+        let hops: Vec<(Address, Address)> = frontier
+            .iter()
+            .flat_map(|from| links.iter().filter(move |(a, _)| a == from).copied())
+            .collect();
+        let quoted = join_all(hops.iter().map(|&(from, to)| quote_link(venues, tokens, from, to, probes[&from], head))).await;
+
+        let mut next = Vec::new();
+        for (&(from, to), outs) in hops.iter().zip(quoted) {
+            let probe = probes[&from];
+            let mut best_out: Option<Decimal> = None;
+            for (venue, out) in outs? {
+                let rate = (out / probe).to_f64().unwrap_or(0.0);
+                if rate <= 0.0 {
+                    continue;
+                }
+                let (a, b) = (graph.node(from), graph.node(to));
+                graph.edges.push(Edge { from: a, to: b, venue, weight: -rate.ln() });
+                best_out = best_out.max(Some(out));
+            }
+            if let Some(out) = best_out {
+                if let Entry::Vacant(entry) = probes.entry(to) {
+                    entry.insert(out);
+                    next.push(to);
+                }
+            }
+        }
+        frontier = next;
+    }
+    Ok(graph)
+}
+
+/// Quote `probe` of `from` into `to` on every venue, in human units of `to`.
+/// Venues whose quote fails are logged and left out.
+async fn quote_link(
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    from: Address,
+    to: Address,
+    probe: Decimal,
+    head: Head,
+) -> Result<Vec<(Arc<dyn Dex>, Decimal)>> {
+    let token_in = tokens.get(from).await?;
+    let token_out = tokens.get(to).await?;
+    let amount_in = token_in.to_base_units(probe)?;
+    let hop = [from, to];
+    let quotes = join_all(venues.iter().map(|venue| venue.quote(amount_in, &hop, head.id()))).await;
+
+    let mut outs = Vec::new();
+    for (venue, quoted) in venues.iter().zip(quotes) {
+        match quoted {
+            Ok(out) => outs.push((venue.clone(), token_out.to_human(out)?)),
+            Err(e) => eprintln!("graph edge {}->{} on {} failed: {:?}", token_in.symbol, token_out.symbol, venue.name(), e),
+        }
+    }
+    Ok(outs)
+}
+
+/// Symbol label for a path, e.g. `USDC>WETH>USDC`.
+pub async fn label(tokens: &TokenRegistry, path: &[Address]) -> Result<String> {
+    let mut symbols = Vec::with_capacity(path.len());
+    for token in path {
+        symbols.push(tokens.get(*token).await?.symbol);
+    }
+    Ok(symbols.join(">"))
+}
+
+impl Graph {
+    fn node(&mut self, token: Address) -> usize {
+        match self.tokens.iter().position(|t| *t == token) {
+            Some(i) => i,
+            None => {
+                self.tokens.push(token);
+                self.tokens.len() - 1
+            }
+        }
+    }
+
+    /// Hop-limited Bellman-Ford from each seed: the cheapest walk of exactly `k` edges back to the seed,
+    /// for k = 2..=`max_hops`, is a candidate when its weight is negative and it visits no token twice.
+    /// Cycles are deduplicated across rotations and returned most negative (most profitable) first.
+    pub fn find_cycles(&self, seeds: &[Seed], max_hops: usize) -> Vec<Candidate> {
+        let n = self.tokens.len();
+        let mut seen = HashSet::new();
+        let mut candidates = Vec::new();
+
+        for seed in seeds {
+            let Some(source) = self.tokens.iter().position(|t| *t == seed.token) else {
+                continue;
+            };
+
+            // dist[k][v]: cheapest walk source -> v with exactly k edges; pred[k][v]: its last edge
+            let mut dist = vec![vec![f64::INFINITY; n]; max_hops + 1];
+            let mut pred: Vec<Vec<Option<usize>>> = vec![vec![None; n]; max_hops + 1];
+            dist[0][source] = 0.0;
+
+            for k in 1..=max_hops {
+                for (i, edge) in self.edges.iter().enumerate() {
+                    let candidate = dist[k - 1][edge.from] + edge.weight;
+                    if candidate < dist[k][edge.to] {
+                        dist[k][edge.to] = candidate;
+                        pred[k][edge.to] = Some(i);
+                    }
+                }
+
+                if k < 2 || dist[k][source] >= 0.0 {
+                    continue;
+                }
+                let Some(edges) = self.walk_back(&pred, k, source) else {
+                    continue;
+                };
+                if !seen.insert(canonical(&edges)) {
+                    continue;
+                }
+                let mut path = vec![self.tokens[source]];
+                path.extend(edges.iter().map(|&e| self.tokens[self.edges[e].to]));
+                candidates.push(Candidate {
+                    path,
+                    venues: edges.iter().map(|&e| self.edges[e].venue.clone()).collect(),
+                    weight: dist[k][source],
+                    trade_amount: seed.trade_amount,
+                    min_profit: seed.min_profit,
+                });
+            }
+        }
+
+        candidates.sort_by(|a, b| a.weight.total_cmp(&b.weight));
+        candidates
+    }
+
+    /// Edge indices of the `k`-edge walk ending at `source`, in travel order, or `None` if it
+    /// revisits a token (then it is not a simple cycle and cannot be executed as priced).
+    fn walk_back(&self, pred: &[Vec<Option<usize>>], k: usize, source: usize) -> Option<Vec<usize>> {
+        let mut edges = Vec::with_capacity(k);
+        let mut visited = HashSet::new();
+        let mut node = source;
+        for level in (1..=k).rev() {
+            let e = pred[level][node]?;
+            edges.push(e);
+            node = self.edges[e].from;
+            if !visited.insert(node) {
+                return None;
+            }
+        }
+        edges.reverse();
+        (node == source).then_some(edges)
+    }
+}
+
+/// Rotation-independent key for a cycle given as edge indices.
+fn canonical(edges: &[usize]) -> Vec<usize> {
+    let start = edges
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, e)| **e)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    edges[start..].iter().chain(&edges[..start]).copied().collect()
+}
@@ -1,17 +1,42 @@
 mod config;
 mod dex;
 mod arbitrage;
+mod token;
+mod triangular;
+mod graph;
+mod blocks;
+mod gas;
+mod sinks;
+mod store;
+mod multicall;
+mod errors;
+mod rpc;
+mod fixture;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod anvil;
+mod backtest;
+mod executor;
 mod web_monitor; // Add your web_monitor module
 
 use ethers::prelude::*;
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
-use csv::WriterBuilder;
-use std::fs::OpenOptions;
+use std::time::{Instant, SystemTime};
 use anyhow::Result;
+use futures::future::join_all;
 use rust_decimal::Decimal;
-use rust_decimal::prelude::{FromPrimitive, ToPrimitive}; // Added ToPrimitive
-use web_monitor::{start_server, ArbData, SharedArbData};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use web_monitor::{start_server, SharedEvaluations, SharedOpportunities, EVALUATION_HISTORY};
+use arbitrage::{Evaluation, Opportunity};
+use sinks::{BlockSummary, CsvSink, DashboardSink, EvaluationCsvSink, Sink};
+use store::{SqliteSink, Store};
+use blocks::{Head, Heads};
+use config::{Config, Discovery};
+use gas::GasCosts;
+use dex::Dex;
+use token::TokenRegistry;
+use triangular::{CycleResult, Leg};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,39 +44,85 @@ async fn main() -> Result<()> {
     let cfg = config::Config::from_env();
     println!("Config: {:?}", cfg);
 
-    // Prepare CSV log file (create if absent, append always)
-    let csv_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .write(true)
-        .open(&cfg.csv_log)?;
-    
-    let mut wtr = WriterBuilder::new()
-        .has_headers(csv_file.metadata()?.len() == 0) // write headers only if file is empty
-        .from_writer(csv_file);
-
-    // Provider
-    let provider = Provider::<Http>::try_from(cfg.rpc_url.as_str())?;
-    let provider = Arc::new(provider);
-
-    // Addresses
-    let dex1_router = cfg.dex1_router;
-    let dex2_router = cfg.dex2_router;
-    let weth = cfg.weth;
-    let usdc = cfg.usdc;
-
-    // Decimal conversions
-    let trade_amount = Decimal::from_f64(cfg.trade_amount_usdc).unwrap_or_else(|| Decimal::from(1000));
-    let min_profit = Decimal::from_f64(cfg.min_profit_usdc).unwrap_or_else(|| Decimal::from(5));
-    let gas_sim_usdc = Decimal::from_f64(5.0).unwrap(); // Fixed gas assumption
+    // `backtest FROM TO [STEP]` replays past blocks against the archive endpoints instead of following the chain
+    let backtest = backtest::Range::from_args(std::env::args().skip(1))?;
+    let rpc_urls = match backtest {
+        Some(_) => &cfg.archive_rpc_urls,
+        None => &cfg.rpc_urls,
+    };
+
+    // Provider over every endpoint, failing over between them, with at most `max_concurrency` requests in flight to each;
+    // or, with REPLAY_FIXTURE, answering from a recorded run without any network
+    let provider = Arc::new(match &cfg.replay_fixture {
+        Some(path) => rpc::replay(path)?,
+        None => rpc::connect(rpc_urls, cfg.max_concurrency, cfg.rpc_quorum, cfg.record_fixture.as_deref())?,
+    });
+
+    let error_stats = errors::ErrorStats::new();
+    let venues = connect_venues(&cfg, &provider, &error_stats);
+    for venue in &venues {
+        println!("Venue {}: {:?} (fee {:?})", venue.name(), venue.kind(), venue.fee());
+    }
+
+    // Token decimals/symbols, fetched on first use
+    let tokens = TokenRegistry::new(provider.clone());
+
+    if let Some(range) = backtest {
+        return backtest::run(&cfg, &provider, &venues, &tokens, range).await;
+    }
+
+    // History database; the dashboard starts from what it already holds
+    let store = match &cfg.db_path {
+        Some(path) => Some(Store::open(path)?),
+        None => None,
+    };
+    let history = match &store {
+        Some(store) => {
+            let mut recent = store.opportunities(&store::Filter::default())?;
+            recent.reverse();
+            println!("Loaded {} opportunities from {}", recent.len(), cfg.db_path.as_deref().unwrap_or_default());
+            recent
+        }
+        None => Vec::new(),
+    };
+    let evaluation_history = match &store {
+        Some(store) => store.recent_evaluations(EVALUATION_HISTORY)?,
+        None => Vec::new(),
+    };
 
     // Shared state for live dashboard
-    let arb_state: SharedArbData = Arc::new(Mutex::new(Vec::new()));
+    let arb_state: SharedOpportunities = Arc::new(Mutex::new(history));
+    let evaluations: SharedEvaluations = Arc::new(Mutex::new(evaluation_history.into()));
+
+    // Every opportunity and evaluation goes to each sink: the CSV logs, the dashboard and the database
+    let mut sinks: Vec<Box<dyn Sink>> = vec![
+        Box::new(CsvSink::open(&cfg.csv_log)?),
+        Box::new(DashboardSink::new(arb_state.clone(), evaluations.clone())),
+    ];
+    if let Some(path) = &cfg.evaluations_log {
+        sinks.push(Box::new(EvaluationCsvSink::open(path)?));
+    }
+    if let Some(store) = &store {
+        sinks.push(Box::new(SqliteSink::new(store.clone())));
+    }
+    // With EXECUTE, robust opportunities are also sent to the arbitrage contract
+    if let Some(execution) = &cfg.execution {
+        let executor = executor::Executor::connect(&cfg, execution, provider.clone(), &tokens).await?;
+        println!("Executing through {:?} as {:?}", execution.contract, executor.address());
+        let log = match &execution.log {
+            Some(path) => Some(executor::ExecutionLog::open(path)?),
+            None => None,
+        };
+        sinks.push(Box::new(executor::spawn(executor, log, store.clone())));
+    }
 
     // Start web server in background
     let web_state = arb_state.clone();
+    let web_evaluations = evaluations.clone();
+    let web_errors = error_stats.clone();
+    let web_store = store.clone();
     tokio::spawn(async move {
-        start_server(web_state).await;
+        start_server(web_state, web_evaluations, web_errors, web_store).await;
     });
 
     // Graceful shutdown handling
@@ -60,53 +131,359 @@ async fn main() -> Result<()> {
         println!("Shutdown signal received...");
     });
 
+    // New blocks: a newHeads subscription when a WebSocket endpoint is configured, polling otherwise
+    let ws = match &cfg.ws_url {
+        Some(url) => Some(Provider::<Ws>::connect(url.as_str()).await?),
+        None => None,
+    };
+    let mut heads = match &ws {
+        Some(ws) => Heads::subscribe(ws).await?,
+        None => Heads::poll(provider.clone(), std::time::Duration::from_secs(cfg.poll_interval_secs)),
+    };
+
+    // With SYNC_ONLY, blocks where none of the watched pairs changed reserves are skipped
+    let watched = if cfg.sync_only {
+        let pools = blocks::watched_pools(&venues, &graph::links(&cfg.pairs, &cfg.cycles)).await;
+        println!("Watching {} pairs for Sync events", pools.len());
+        pools
+    } else {
+        Vec::new()
+    };
+
     loop {
+        let head = heads.next().await?;
+
         // Check for shutdown signal
         if shutdown.is_finished() {
             println!("Shutting down gracefully...");
             break;
         }
 
-        // Best direction and profit
-        match arbitrage::best_direction(
-            provider.clone(),
-            dex1_router,
-            dex2_router,
-            usdc,
-            weth,
-            trade_amount,
-            gas_sim_usdc,
-        ).await {
-            Ok(Some((dir, profit))) => {
-                let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
-                if profit >= min_profit {
-                    println!("🚀 [{ts}] Arb found! direction={} profit={} USDC", dir, profit);
-                    // Append to CSV
-                    wtr.write_record(&[ts.to_string(), dir.clone(), profit.to_string()])?;
-                    wtr.flush()?;
-
-                    // Update web dashboard
-                    {
-                        let mut data = arb_state.lock().unwrap();
-                        data.push(ArbData { timestamp: ts, direction: dir.clone(), profit_usdc: profit.to_f64().unwrap_or(0.0) });
+        if !watched.is_empty() {
+            match blocks::has_sync(&provider, head, &watched).await {
+                Ok(false) => continue,
+                Ok(true) => {}
+                Err(e) => eprintln!("Failed to check Sync events in block {}: {:?}", head.number, e),
+            }
+        }
+
+        let gas = gas_costs(&cfg, &provider, &venues, &tokens, head).await;
+
+        let started = Instant::now();
+        let requests = provider.as_ref().as_ref().requests();
+        let opportunities = evaluate(&cfg, &venues, &tokens, &gas, head, &mut sinks).await.unwrap_or_else(|e| {
+            eprintln!("Error during evaluation: {:?}", e);
+            0
+        });
+        let summary = BlockSummary {
+            block_number: head.number.as_u64(),
+            block_hash: format!("{:?}", head.hash),
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs(),
+            gas_gwei: gas.gwei().to_f64().unwrap_or_default(),
+            evaluation_ms: started.elapsed().as_millis() as u64,
+            rpc_requests: provider.as_ref().as_ref().requests() - requests,
+            opportunities,
+        };
+        println!(
+            "⏱ block {} evaluated in {}ms ({} RPC requests, {}ms since the head arrived)",
+            head.number,
+            summary.evaluation_ms,
+            summary.rpc_requests,
+            head.seen.elapsed().as_millis()
+        );
+        for sink in &mut sinks {
+            if let Err(e) = sink.evaluated(&summary) {
+                eprintln!("Failed to record block {}: {:?}", head.number, e);
+            }
+        }
+    }
+
+    // Final flush before exit
+    for sink in &mut sinks {
+        sink.flush()?;
+    }
+    Ok(())
+}
+
+/// Every configured venue, batched through Multicall3 when enabled and retried per failure class.
+fn connect_venues(cfg: &Config, provider: &Arc<rpc::Client>, error_stats: &Arc<errors::ErrorStats>) -> Vec<Arc<dyn Dex>> {
+    let mut venues: Vec<Arc<dyn Dex>> = cfg
+        .venues
+        .iter()
+        .map(|venue| dex::connect(venue, provider.clone()))
+        .collect();
+
+    // Batch every venue's quotes for an evaluation into Multicall3 calls pinned to one block
+    if let Some(address) = cfg.multicall {
+        let batcher = multicall::Batcher::new(address, provider.clone());
+        venues = venues.into_iter().map(|venue| multicall::batched(venue, batcher.clone())).collect();
+    }
+    // Classify, retry and count every venue failure; outermost so retried quotes are batched again
+    venues.into_iter().map(|venue| errors::retrying(venue, error_stats.clone())).collect()
+}
+
+/// Current gas price in every token profits are measured in, or the fixed fallback cost.
+async fn gas_costs(cfg: &Config, provider: &rpc::Client, venues: &[Arc<dyn Dex>], tokens: &TokenRegistry, head: Head) -> GasCosts {
+    let quotes: Vec<Address> = graph::seeds(&cfg.pairs, &cfg.cycles).iter().map(|seed| seed.token).collect();
+    let gas = match gas::estimate(provider, venues, tokens, &cfg.gas, &quotes, head).await {
+        Ok(gas) => gas,
+        Err(e) => {
+            eprintln!("Failed to estimate gas, using the fallback cost: {:?}", e);
+            GasCosts::fixed(&cfg.gas)
+        }
+    };
+    println!("⛽ block {} gas {} gwei", head.number, gas.gwei().round_dp(2));
+    gas
+}
+
+/// Evaluate one block the configured way and hand the results to `sinks`. Returns how many
+/// opportunities were recorded.
+async fn evaluate(
+    cfg: &Config,
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    gas: &GasCosts,
+    head: Head,
+    sinks: &mut [Box<dyn Sink>],
+) -> Result<usize> {
+    match cfg.discovery {
+        Discovery::Routes => evaluate_routes(cfg, venues, tokens, gas, head, sinks).await,
+        Discovery::Graph => discover_cycles(cfg, venues, tokens, gas, head, sinks).await,
+    }
+}
+
+/// Hand an opportunity to every sink; a failing sink is logged and does not stop the others.
+fn record_opportunity(sinks: &mut [Box<dyn Sink>], opportunity: &Opportunity) {
+    for sink in sinks {
+        if let Err(e) = sink.record(opportunity) {
+            eprintln!("Failed to record opportunity {}: {:?}", opportunity.id, e);
+        }
+    }
+}
+
+/// Hand an evaluation to every sink, unless its block falls outside the sample. Profitable
+/// evaluations are always recorded. A failing sink is logged and does not stop the others.
+fn record_evaluation(sinks: &mut [Box<dyn Sink>], evaluation: &Evaluation, sample: u64) {
+    if !evaluation.block_number.is_multiple_of(sample) && !evaluation.profitable() {
+        return;
+    }
+    for sink in sinks {
+        if let Err(e) = sink.evaluation(evaluation) {
+            eprintln!("Failed to record evaluation of {} at block {}: {:?}", evaluation.pair, evaluation.block_number, e);
+        }
+    }
+}
+
+/// Rank the two-leg routes of every pair and price every configured cycle, concurrently.
+/// Returns how many opportunities were recorded.
+async fn evaluate_routes(
+    cfg: &Config,
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    gas: &GasCosts,
+    head: Head,
+    sinks: &mut [Box<dyn Sink>],
+) -> Result<usize> {
+    // Every buy/sell venue combination for every pair, evaluated concurrently
+    let (results, cycle_results) = tokio::join!(
+        join_all(
+            cfg.pairs
+                .iter()
+                .map(|pair| arbitrage::rank_routes(venues, tokens, pair, gas, cfg.optimize_size, head)),
+        ),
+        join_all(
+            cfg.cycles
+                .iter()
+                .map(|cycle| triangular::evaluate_cycle(venues, tokens, cycle, gas, head)),
+        ),
+    );
+    let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+    let mut recorded = 0;
+
+    for (pair, result) in cfg.pairs.iter().zip(results) {
+        let min_profit = Decimal::from_f64(pair.min_profit).unwrap_or_else(|| Decimal::from(5));
+        // A malformed route only costs its own pair, never the pairs and cycles after it
+        if let Ok(routes) = &result {
+            match Evaluation::from_routes(&pair.name, routes, min_profit, head) {
+                Ok(evaluation) => record_evaluation(sinks, &evaluation, cfg.evaluation_sample),
+                Err(e) => eprintln!("Failed to build the evaluation of {}: {:?}", pair.name, e),
+            }
+        }
+        match result {
+            Ok(routes) => match routes.first() {
+                None => println!("— [{ts}] {} no valid simulation results (all venues failed)", pair.name),
+                Some(best) if best.best_profit() < min_profit => {
+                    println!("— [{ts}] {} no arb (best {} profit={}, {} routes)", pair.name, best.direction(), best.best_profit(), routes.len());
+                }
+                Some(_) => {
+                    for route in routes.iter().filter(|route| route.best_profit() >= min_profit) {
+                        let opportunity = match Opportunity::from_route(route, cfg.slippage_bps, min_profit) {
+                            Ok(opportunity) => opportunity,
+                            Err(e) => {
+                                eprintln!("Failed to build the {} {} opportunity: {:?}", pair.name, route.direction(), e);
+                                continue;
+                            }
+                        };
+                        println!(
+                            "🚀 [{ts}] Arb found! block={} pair={} direction={} amount={} {} profit={} {} (after gas {})",
+                            route.block.number, route.pair, route.direction(), opportunity.intermediate_amount, route.base_symbol, route.best_profit(), route.quote_symbol, route.gas_cost.round_dp(4)
+                        );
+                        println!("   legs=[{}] {}", describe_legs(route.best_legs()), describe_slippage(&opportunity, cfg.slippage_bps));
+                        if let Some(sizing) = &route.sizing {
+                            let curve: Vec<String> = sizing.curve.iter().map(|(amount, profit)| format!("{amount}:{profit}")).collect();
+                            println!(
+                                "   optimal size={} {} max profit={} ({}) curve=[{}]",
+                                sizing.optimal_amount,
+                                route.quote_symbol,
+                                sizing.max_profit,
+                                if sizing.closed_form { "closed form" } else { "search" },
+                                curve.join(", ")
+                            );
+                        }
+                        record_opportunity(sinks, &opportunity);
+                        recorded += 1;
                     }
-                } else {
-                    println!("— [{ts}] no arb (best {} profit={})", dir, profit);
                 }
+            },
+            Err(e) => {
+                eprintln!("Error during simulate ({}): {:?}", pair.name, e);
             }
-            Ok(None) => {
-                let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
-                println!("— [{ts}] no valid simulation results (both directions failed)");
+        }
+    }
+
+    for (cycle, result) in cfg.cycles.iter().zip(cycle_results) {
+        let min_profit = Decimal::from_f64(cycle.min_profit).unwrap_or_else(|| Decimal::from(5));
+        match result {
+            Ok(priced) => {
+                match Evaluation::from_cycles(&cycle.name, &priced, min_profit, head) {
+                    Ok(evaluation) => record_evaluation(sinks, &evaluation, cfg.evaluation_sample),
+                    Err(e) => eprintln!("Failed to build the evaluation of {}: {:?}", cycle.name, e),
+                }
+                recorded += report_cycles(ts, &cycle.name, min_profit, cfg.slippage_bps, &priced, sinks);
             }
             Err(e) => {
-                eprintln!("Error during simulate: {:?}", e);
+                eprintln!("Error during cycle simulate ({}): {:?}", cycle.name, e);
             }
         }
+    }
+
+    Ok(recorded)
+}
 
-        tokio::time::sleep(std::time::Duration::from_secs(cfg.poll_interval_secs)).await;
+/// Build the token/pool graph from every venue, pair and cycle, search it for negative cycles
+/// and price each candidate exactly with its discovered venues. Returns how many opportunities were recorded.
+async fn discover_cycles(
+    cfg: &Config,
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    gas: &GasCosts,
+    head: Head,
+    sinks: &mut [Box<dyn Sink>],
+) -> Result<usize> {
+    let seeds = graph::seeds(&cfg.pairs, &cfg.cycles);
+    let links = graph::links(&cfg.pairs, &cfg.cycles);
+    let graph = graph::build(venues, tokens, &links, &seeds, head).await?;
+    let candidates = graph.find_cycles(&seeds, cfg.max_hops);
+    let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+
+    if candidates.is_empty() {
+        println!("— [{ts}] no negative cycles ({} tokens, {} edges)", graph.tokens.len(), graph.edges.len());
+    }
+    // Candidates are independent: price them all at once
+    let simulated = join_all(candidates.iter().map(|candidate| async move {
+        let name = graph::label(tokens, &candidate.path).await?;
+        let result = triangular::simulate_route(tokens, &name, &candidate.path, &candidate.venues, candidate.trade_amount, gas, head).await;
+        anyhow::Ok((name, result))
+    }))
+    .await;
+    let mut recorded = 0;
+    for (candidate, simulated) in candidates.iter().zip(simulated) {
+        let (name, result) = match simulated {
+            Ok(simulated) => simulated,
+            Err(e) => {
+                eprintln!("Error labelling candidate (weight {:.6}): {:?}", candidate.weight, e);
+                continue;
+            }
+        };
+        match result {
+            Ok(result) => {
+                let priced = [result];
+                match Evaluation::from_cycles(&name, &priced, candidate.min_profit, head) {
+                    Ok(evaluation) => record_evaluation(sinks, &evaluation, cfg.evaluation_sample),
+                    Err(e) => eprintln!("Failed to build the evaluation of {}: {:?}", name, e),
+                }
+                recorded += report_cycles(ts, &name, candidate.min_profit, cfg.slippage_bps, &priced, sinks);
+            }
+            Err(e) => eprintln!("Error simulating candidate {} (weight {:.6}): {:?}", name, candidate.weight, e),
+        }
     }
+    Ok(recorded)
+}
 
-    // Final flush before exit
-    wtr.flush()?;
-    Ok(())
+/// Log priced cycles (best first) and record those that clear `min_profit`; one that cannot be
+/// recorded is logged and skipped. Returns how many were recorded.
+fn report_cycles(
+    ts: u64,
+    name: &str,
+    min_profit: Decimal,
+    slippage_bps: u32,
+    priced: &[CycleResult],
+    sinks: &mut [Box<dyn Sink>],
+) -> usize {
+    let mut recorded = 0;
+    match priced.first() {
+        None => println!("— [{ts}] {} no valid cycle results (all venues failed)", name),
+        Some(best) if best.profit < min_profit => {
+            println!("— [{ts}] {} no arb (best {} profit={})", name, best.route(), best.profit);
+        }
+        Some(_) => {
+            for result in priced.iter().filter(|result| result.profit >= min_profit) {
+                let opportunity = match Opportunity::from_cycle(result, slippage_bps, min_profit) {
+                    Ok(opportunity) => opportunity,
+                    Err(e) => {
+                        eprintln!("Failed to build the {} {} opportunity: {:?}", name, result.route(), e);
+                        continue;
+                    }
+                };
+                println!(
+                    "🔺 [{ts}] Cycle arb found! block={} cycle={} profit={} {} legs=[{}] {}",
+                    result.block.number,
+                    result.cycle,
+                    result.profit,
+                    result.quote_symbol,
+                    describe_legs(&result.legs),
+                    describe_slippage(&opportunity, slippage_bps)
+                );
+                record_opportunity(sinks, &opportunity);
+                recorded += 1;
+            }
+        }
+    }
+    recorded
+}
+
+/// One line per leg: amounts, venue, mid and execution price and price impact.
+fn describe_legs(legs: &[Leg]) -> String {
+    legs.iter()
+        .map(|leg| {
+            format!(
+                "{} {} -> {} {} on {} (mid {} exec {} impact {}%)",
+                leg.amount_in,
+                leg.token_in,
+                leg.amount_out,
+                leg.token_out,
+                leg.venue,
+                leg.mid_price.round_dp(6),
+                leg.execution_price().round_dp(6),
+                (leg.price_impact * Decimal::from(100)).round_dp(3)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Profit at the slippage tolerance and whether the opportunity survives it.
+fn describe_slippage(opportunity: &Opportunity, slippage_bps: u32) -> String {
+    let verdict = if opportunity.robust { "robust" } else { "fragile" };
+    format!("profit at {slippage_bps}bps slippage={:.6} ({verdict}) latency={}ms", opportunity.slippage_profit, opportunity.latency_ms)
 }
@@ -0,0 +1,148 @@
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use anyhow::Result;
+use async_trait::async_trait;
+use crate::arbitrage::{Evaluation, Opportunity};
+use crate::blocks::Head;
+use crate::dex::{get_amount_out, Dex, DexKind};
+use crate::errors::SimError;
+use crate::fixture::Replay;
+use crate::rpc::{Client, Transport};
+use crate::sinks::Sink;
+use crate::token::{TokenInfo, TokenRegistry};
+
+/// In-memory constant-product venue: a fee, reserves per pool and failures on demand, quoted with
+/// the same math as a UniswapV2 router.
+pub struct MockDex {
+    name: String,
+    fee: u32,
+    /// `(token_in, token_out) -> (reserve_in, reserve_out)`, both directions of every pool
+    pools: Mutex<HashMap<(Address, Address), (U256, U256)>>,
+    /// Every call fails with this until `recover`
+    failure: Mutex<Option<SimError>>,
+}
+
+impl MockDex {
+    /// Venue charging `fee` in hundredths of a bip (3000 = 0.3%)
+    pub fn new(name: &str, fee: u32) -> Self {
+        MockDex {
+            name: name.to_string(),
+            fee,
+            pools: Mutex::new(HashMap::new()),
+            failure: Mutex::new(None),
+        }
+    }
+
+    /// Add (or replace) the `token_a`/`token_b` pool holding `reserve_a` and `reserve_b` base units
+    pub fn with_pool(self, token_a: Address, token_b: Address, reserve_a: U256, reserve_b: U256) -> Self {
+        self.set_pool(token_a, token_b, reserve_a, reserve_b);
+        self
+    }
+
+    pub fn set_pool(&self, token_a: Address, token_b: Address, reserve_a: U256, reserve_b: U256) {
+        let mut pools = self.pools.lock().unwrap();
+        pools.insert((token_a, token_b), (reserve_a, reserve_b));
+        pools.insert((token_b, token_a), (reserve_b, reserve_a));
+    }
+
+    /// Fail every call from now on with `error`
+    pub fn fail_with(&self, error: SimError) {
+        *self.failure.lock().unwrap() = Some(error);
+    }
+
+    pub fn recover(&self) {
+        *self.failure.lock().unwrap() = None;
+    }
+
+    fn call(&self) -> Result<()> {
+        match self.failure.lock().unwrap().clone() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn reserves_of(&self, token_in: Address, token_out: Address) -> Result<(U256, U256)> {
+        self.pools
+            .lock()
+            .unwrap()
+            .get(&(token_in, token_out))
+            .copied()
+            .ok_or_else(|| SimError::NoPool(format!("{} has no {token_in:?}/{token_out:?} pool", self.name)).into())
+    }
+}
+
+#[async_trait]
+impl Dex for MockDex {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn kind(&self) -> DexKind {
+        DexKind::UniswapV2
+    }
+
+    fn fee(&self) -> Option<u32> {
+        Some(self.fee)
+    }
+
+    async fn quote(&self, amount_in: U256, path: &[Address], _block: BlockId) -> Result<U256> {
+        self.call()?;
+        let mut amount = amount_in;
+        for hop in path.windows(2) {
+            let (reserve_in, reserve_out) = self.reserves_of(hop[0], hop[1])?;
+            amount = get_amount_out(amount, reserve_in, reserve_out, self.fee)
+                .map_err(|e| SimError::Revert(format!("INSUFFICIENT_LIQUIDITY: {e}")))?;
+        }
+        Ok(amount)
+    }
+
+    async fn reserves(&self, token_in: Address, token_out: Address, _block: BlockId) -> Result<Option<(U256, U256)>> {
+        self.call()?;
+        Ok(self.reserves_of(token_in, token_out).ok())
+    }
+}
+
+/// Token registry that already knows `tokens` as `(address, symbol, decimals)` and has no network
+/// to look up any other.
+pub fn tokens(tokens: &[(Address, &str, u8)]) -> TokenRegistry {
+    let registry = TokenRegistry::new(Arc::new(offline()));
+    for (address, symbol, decimals) in tokens {
+        registry.insert(*address, TokenInfo { symbol: symbol.to_string(), decimals: *decimals });
+    }
+    registry
+}
+
+/// Provider that answers nothing
+pub fn offline() -> Client {
+    Provider::new(Transport::Replay(Replay::default()))
+}
+
+pub fn head(number: u64) -> Head {
+    Head { number: number.into(), hash: H256::from_low_u64_be(number), seen: Instant::now() }
+}
+
+/// `amount` whole tokens of `decimals` in base units
+pub fn units(amount: u64, decimals: u8) -> U256 {
+    U256::from(amount) * U256::exp10(decimals as usize)
+}
+
+/// Sink keeping everything it is handed, for assertions.
+#[derive(Clone, Default)]
+pub struct Collected {
+    pub opportunities: Arc<Mutex<Vec<Opportunity>>>,
+    pub evaluations: Arc<Mutex<Vec<Evaluation>>>,
+}
+
+impl Sink for Collected {
+    fn record(&mut self, opportunity: &Opportunity) -> Result<()> {
+        self.opportunities.lock().unwrap().push(opportunity.clone());
+        Ok(())
+    }
+
+    fn evaluation(&mut self, evaluation: &Evaluation) -> Result<()> {
+        self.evaluations.lock().unwrap().push(evaluation.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use tokio::sync::oneshot;
+use crate::dex::{Dex, DexKind};
+use crate::errors::{revert_reason, SimError};
+use crate::rpc::Client;
+
+// Abigen for Multicall3 (deployed at the same address on every major chain)
+abigen!(
+    Multicall3,
+    r#"[
+        struct Call3 { address target; bool allowFailure; bytes callData; }
+        struct Call3Result { bool success; bytes returnData; }
+        function aggregate3(Call3[] calldata calls) external payable returns (Call3Result[] memory returnData)
+    ]"#
+);
+
+/// Canonical Multicall3 deployment
+pub const MULTICALL3_ADDRESS: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+/// Most calls sent in one `aggregate3`; bigger batches are split but stay on the same block
+const MAX_BATCH: usize = 300;
+/// How long calls are collected before a batch is sent
+const BATCH_WINDOW: Duration = Duration::from_millis(2);
+
+/// One eth_call: the block it is pinned to, target and calldata
+type Key = (BlockId, Address, Bytes);
+type Pending = Vec<(Key, oneshot::Sender<Result<Bytes, SimError>>)>;
+
+/// Collects eth_calls issued concurrently during an evaluation and sends them as one Multicall3
+/// `aggregate3` (failures allowed per call) per block they are pinned to. Identical calls at the
+/// same block are answered from a cache, so re-reading reserves costs nothing.
+pub struct Batcher {
+    contract: Multicall3<Client>,
+    pending: Mutex<Pending>,
+    cache: Mutex<HashMap<Key, Bytes>>,
+}
+
+impl Batcher {
+    pub fn new(address: Address, provider: Arc<Client>) -> Arc<Self> {
+        Arc::new(Batcher {
+            contract: Multicall3::new(address, provider),
+            pending: Mutex::new(Vec::new()),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Queue one call at `block` and wait for its return data.
+    pub async fn call(self: &Arc<Self>, block: BlockId, target: Address, data: Bytes) -> Result<Bytes> {
+        let key = (block, target, data);
+        if let Some(hit) = self.cache.lock().unwrap().get(&key) {
+            return Ok(hit.clone());
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let first = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push((key, tx));
+            pending.len() == 1
+        };
+        if first {
+            let batcher = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(BATCH_WINDOW).await;
+                batcher.flush().await;
+            });
+        }
+
+        Ok(rx.await.map_err(|_| anyhow!("multicall batch dropped"))??)
+    }
+
+    async fn flush(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let mut blocks: Vec<BlockId> = Vec::new();
+        for ((block, _, _), _) in &pending {
+            if !blocks.contains(block) {
+                blocks.push(*block);
+            }
+        }
+        // Results from blocks nobody is asking about any more will not be asked for again
+        self.cache.lock().unwrap().retain(|(block, _, _), _| blocks.contains(block));
+
+        let mut failed = HashMap::new();
+        for block in blocks {
+            let keys: Vec<&Key> = pending.iter().map(|(key, _)| key).filter(|key| key.0 == block).collect();
+            for chunk in keys.chunks(MAX_BATCH) {
+                let calls: Vec<Call3> = chunk
+                    .iter()
+                    .map(|(_, target, data)| Call3 { target: *target, allow_failure: true, call_data: data.clone() })
+                    .collect();
+                let results = match self.contract.aggregate_3(calls).block(block).call().await {
+                    Ok(results) => results,
+                    Err(e) => {
+                        // The batch itself failed (RPC trouble, not the calls): every call in it shares the error
+                        let error = SimError::classify(&e.into());
+                        eprintln!("multicall aggregate3 of {} calls failed: {}", chunk.len(), error);
+                        for key in chunk {
+                            failed.insert((*key).clone(), error.clone());
+                        }
+                        continue;
+                    }
+                };
+                let mut cache = self.cache.lock().unwrap();
+                for (key, (success, data)) in chunk.iter().zip(results) {
+                    if success {
+                        cache.insert((*key).clone(), data);
+                    } else {
+                        let reason = format!("call to {:?}: {}", key.1, revert_reason(&data));
+                        failed.insert((*key).clone(), SimError::Revert(reason));
+                    }
+                }
+            }
+        }
+
+        // Answer every caller, including duplicates of the same call
+        let cache = self.cache.lock().unwrap();
+        for (key, tx) in pending {
+            let result = match cache.get(&key) {
+                Some(data) => Ok(data.clone()),
+                None => Err(failed
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| SimError::Other(format!("call to {:?} got no result", key.1)))),
+            };
+            let _ = tx.send(result);
+        }
+    }
+}
+
+/// A venue whose quotes go through a shared `Batcher` whenever it can express them as plain calls.
+pub struct BatchedDex {
+    inner: Arc<dyn Dex>,
+    batcher: Arc<Batcher>,
+}
+
+/// Wrap `venue` so its quotes are batched.
+pub fn batched(venue: Arc<dyn Dex>, batcher: Arc<Batcher>) -> Arc<dyn Dex> {
+    Arc::new(BatchedDex { inner: venue, batcher })
+}
+
+#[async_trait]
+impl Dex for BatchedDex {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn kind(&self) -> DexKind {
+        self.inner.kind()
+    }
+
+    fn fee(&self) -> Option<u32> {
+        self.inner.fee()
+    }
+
+    async fn quote(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<U256> {
+        let Some(calls) = self.inner.quote_calls(amount_in, path) else {
+            return self.inner.quote(amount_in, path, block).await;
+        };
+        let results = join_all(calls.into_iter().map(|(target, data)| self.batcher.call(block, target, data))).await;
+        let results = results.into_iter().collect::<Result<Vec<_>>>()?;
+        self.inner.decode_quote(amount_in, path, &results)
+    }
+
+    fn quote_calls(&self, amount_in: U256, path: &[Address]) -> Option<Vec<(Address, Bytes)>> {
+        self.inner.quote_calls(amount_in, path)
+    }
+
+    fn decode_quote(&self, amount_in: U256, path: &[Address], results: &[Bytes]) -> Result<U256> {
+        self.inner.decode_quote(amount_in, path, results)
+    }
+
+    async fn reserves(&self, token_in: Address, token_out: Address, block: BlockId) -> Result<Option<(U256, U256)>> {
+        self.inner.reserves(token_in, token_out, block).await
+    }
+
+    async fn pool(&self, token_a: Address, token_b: Address) -> Result<Option<Address>> {
+        self.inner.pool(token_a, token_b).await
+    }
+
+    /// The amount after each hop is the quote of the path up to that hop. Those quotes do not
+    /// depend on each other, so they all go out in the same batch instead of one hop after another.
+    async fn quote_hops(&self, amount_in: U256, path: &[Address], block: BlockId) -> Result<Vec<U256>> {
+        if self.inner.quote_calls(amount_in, path).is_none() {
+            return self.inner.quote_hops(amount_in, path, block).await;
+        }
+        let hops = join_all((2..=path.len()).map(|end| self.quote(amount_in, &path[..end], block))).await;
+        let mut amounts = vec![amount_in];
+        for amount in hops {
+            amounts.push(amount?);
+        }
+        Ok(amounts)
+    }
+}
@@ -0,0 +1,429 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, JsonRpcError, Provider, ProviderError, RpcError};
+use futures::future::join_all;
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use crate::fixture::{Recorder, Replay};
+
+/// Provider every component talks to the HTTP endpoints (or a replayed fixture) through.
+pub type Client = Provider<Transport>;
+
+/// Node errors (code -32000) meaning the endpoint lacks the requested block or its state
+const LAGGING_NODE: [&str; 5] =
+    ["header not found", "unknown block", "missing trie node", "state not available", "state unavailable"];
+
+/// Give up on a request to one endpoint after this long
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// First backoff after an endpoint fails; doubled for every further consecutive failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Connect to every endpoint in `urls` (in order of preference), allowing at most `max_in_flight`
+/// concurrent requests to each. With `quorum` > 1, every `eth_call` must get the same answer from
+/// that many endpoints. With `record`, every answer is also appended to that fixture file.
+pub fn connect(urls: &[String], max_in_flight: usize, quorum: usize, record: Option<&str>) -> anyhow::Result<Client> {
+    let http = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+    let endpoints = urls
+        .iter()
+        .map(|url| Endpoint::new(url, http.clone(), max_in_flight))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(!endpoints.is_empty(), "no RPC endpoints configured");
+    anyhow::ensure!(
+        (1..=endpoints.len()).contains(&quorum),
+        "quorum of {quorum} needs between 1 and {} endpoints",
+        endpoints.len()
+    );
+    let failover = Failover { endpoints, quorum, next_id: AtomicU64::new(1), requests: AtomicU64::new(0) };
+    let recorder = record.map(Recorder::create).transpose()?;
+    Ok(Provider::new(Transport::Live { failover, recorder }))
+}
+
+/// Answer every request from a fixture recorded with `connect(.., Some(path))`, without any network.
+pub fn replay(path: &str) -> anyhow::Result<Client> {
+    Ok(Provider::new(Transport::Replay(Replay::load(path)?)))
+}
+
+/// Why a request failed.
+#[derive(Debug, Error)]
+pub enum RpcFailure {
+    #[error("{endpoint} is rate limiting")]
+    RateLimited { endpoint: String },
+    #[error("{endpoint} answered HTTP {status}")]
+    Server { endpoint: String, status: StatusCode },
+    #[error("{endpoint} timed out")]
+    Timeout { endpoint: String },
+    #[error("{endpoint} is unreachable: {source}")]
+    Transport { endpoint: String, source: reqwest::Error },
+    /// The node answered with an error, e.g. an `eth_call` revert
+    #[error(transparent)]
+    JsonRpc(JsonRpcError),
+    #[error("response could not be decoded: {err}. Response: {text}")]
+    Decode { err: serde_json::Error, text: String },
+    #[error("{endpoints} disagree on {method}")]
+    Disagreement { method: String, endpoints: String },
+    /// Replaying a fixture that holds no answer for the request
+    #[error("no recorded answer for {method}")]
+    Unrecorded { method: String },
+}
+
+impl RpcFailure {
+    /// Failures that say something about the endpoint rather than the request; these trigger
+    /// backoff and failover to the next endpoint.
+    fn is_endpoint_fault(&self) -> bool {
+        matches!(
+            self,
+            RpcFailure::RateLimited { .. } | RpcFailure::Server { .. } | RpcFailure::Timeout { .. } | RpcFailure::Transport { .. }
+        )
+    }
+
+    /// A node that has not caught up with (or has pruned) the requested block; another endpoint may
+    /// answer, so these fail over too, without backoff. Reverts never do: every node agrees on them.
+    fn is_lagging_node(&self) -> bool {
+        let RpcFailure::JsonRpc(error) = self else {
+            return false;
+        };
+        let message = error.message.to_lowercase();
+        error.code != 3
+            && error.data.is_none()
+            && LAGGING_NODE.iter().any(|phrase| message.contains(phrase))
+    }
+
+    fn fails_over(&self) -> bool {
+        self.is_endpoint_fault() || self.is_lagging_node()
+    }
+}
+
+impl RpcError for RpcFailure {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            RpcFailure::JsonRpc(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            RpcFailure::Decode { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<RpcFailure> for ProviderError {
+    fn from(failure: RpcFailure) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(failure))
+    }
+}
+
+/// One HTTP endpoint with its own concurrency cap and health.
+#[derive(Debug)]
+struct Endpoint {
+    url: Url,
+    /// Host only, so API keys in the URL stay out of the logs
+    label: String,
+    http: reqwest::Client,
+    permits: Semaphore,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Consecutive endpoint faults
+    failures: u32,
+    /// Not tried again before this, unless every endpoint is backing off
+    retry_at: Option<Instant>,
+}
+
+impl Endpoint {
+    fn new(url: &str, http: reqwest::Client, max_in_flight: usize) -> anyhow::Result<Self> {
+        let url = Url::parse(url)?;
+        let label = url.host_str().unwrap_or("rpc").to_string();
+        Ok(Endpoint { url, label, http, permits: Semaphore::new(max_in_flight.max(1)), health: Mutex::default() })
+    }
+
+    /// When this endpoint may be tried again; `None` when healthy
+    fn retry_at(&self) -> Option<Instant> {
+        self.health.lock().unwrap().retry_at.filter(|at| *at > Instant::now())
+    }
+
+    async fn send(&self, id: u64, method: &str, params: &Value) -> Result<Value, RpcFailure> {
+        // Only reached for a backing-off endpoint once every healthier one has failed
+        if let Some(at) = self.retry_at() {
+            tokio::time::sleep_until(at.into()).await;
+        }
+        let result = {
+            // The semaphore is never closed
+            let _permit = self.permits.acquire().await.expect("request semaphore closed");
+            self.post(id, method, params).await
+        };
+        match &result {
+            Err(failure) if failure.is_endpoint_fault() => self.failed(failure),
+            _ => self.succeeded(),
+        }
+        result
+    }
+
+    async fn post(&self, id: u64, method: &str, params: &Value) -> Result<Value, RpcFailure> {
+        let endpoint = || self.label.clone();
+        let transport = |source: reqwest::Error| match source.is_timeout() {
+            true => RpcFailure::Timeout { endpoint: endpoint() },
+            false => RpcFailure::Transport { endpoint: endpoint(), source },
+        };
+
+        let body = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let response = self.http.post(self.url.clone()).json(&body).send().await.map_err(transport)?;
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(RpcFailure::RateLimited { endpoint: endpoint() });
+        }
+        if status.is_server_error() {
+            return Err(RpcFailure::Server { endpoint: endpoint(), status });
+        }
+
+        let text = response.text().await.map_err(transport)?;
+        let mut reply: Value = serde_json::from_str(&text).map_err(|err| RpcFailure::Decode { err, text: text.clone() })?;
+        if let Some(error) = reply.get("error") {
+            let error: JsonRpcError =
+                serde_json::from_value(error.clone()).map_err(|err| RpcFailure::Decode { err, text: text.clone() })?;
+            return Err(match is_rate_limit(&error) {
+                true => RpcFailure::RateLimited { endpoint: endpoint() },
+                false => RpcFailure::JsonRpc(error),
+            });
+        }
+        match reply.get_mut("result") {
+            Some(result) => Ok(result.take()),
+            None => Err(RpcFailure::Decode { err: serde::de::Error::missing_field("result"), text }),
+        }
+    }
+
+    fn failed(&self, failure: &RpcFailure) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        let backoff = INITIAL_BACKOFF.saturating_mul(1 << (health.failures - 1).min(16)).min(MAX_BACKOFF);
+        health.retry_at = Some(Instant::now() + backoff);
+        eprintln!("RPC {failure}; backing off {}ms", backoff.as_millis());
+    }
+
+    fn succeeded(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.failures > 0 {
+            eprintln!("RPC {} recovered after {} failures", self.label, health.failures);
+        }
+        *health = Health::default();
+    }
+}
+
+/// Rate limits some providers report as JSON-RPC errors instead of HTTP 429.
+fn is_rate_limit(error: &JsonRpcError) -> bool {
+    error.code == 429 || error.code == -32005 || error.message.to_lowercase().contains("rate limit")
+}
+
+/// Sends each request to the healthiest endpoint and fails over to the next on rate limits, 5xx
+/// answers, timeouts, connection errors and lagging nodes ("header not found"); other node errors such
+/// as reverts are returned from the first endpoint. Failing endpoints back off exponentially.
+#[derive(Debug)]
+pub struct Failover {
+    endpoints: Vec<Endpoint>,
+    /// Endpoints that must return the same `eth_call` result
+    quorum: usize,
+    next_id: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl Failover {
+    /// Requests sent since startup
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Healthy endpoints in configured order, then backing-off ones soonest first
+    fn by_health(&self) -> Vec<&Endpoint> {
+        let mut endpoints: Vec<(Option<Instant>, &Endpoint)> =
+            self.endpoints.iter().map(|endpoint| (endpoint.retry_at(), endpoint)).collect();
+        // Stable: None sorts first and keeps the configured order
+        endpoints.sort_by_key(|(retry_at, _)| *retry_at);
+        endpoints.into_iter().map(|(_, endpoint)| endpoint).collect()
+    }
+
+    async fn send(&self, method: &str, params: &Value) -> Result<Value, RpcFailure> {
+        // Quotes are what opportunities are built from, so only they need confirming
+        match method {
+            "eth_call" if self.quorum > 1 => self.agreed(method, params).await,
+            _ => self.first(method, params).await,
+        }
+    }
+
+    /// First answer from the healthiest endpoint that gives one.
+    async fn first(&self, method: &str, params: &Value) -> Result<Value, RpcFailure> {
+        let mut last = None;
+        for endpoint in self.by_health() {
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            match endpoint.send(self.next_id.fetch_add(1, Ordering::Relaxed), method, params).await {
+                Err(failure) if failure.fails_over() => keep(&mut last, failure),
+                result => return result,
+            }
+        }
+        Err(last.expect("at least one endpoint"))
+    }
+
+    /// The same answer from `quorum` endpoints, replacing endpoints that fail with the next healthiest.
+    async fn agreed(&self, method: &str, params: &Value) -> Result<Value, RpcFailure> {
+        let mut remaining = self.by_health().into_iter();
+        let mut answers: Vec<(&Endpoint, Value)> = Vec::new();
+        let mut last = None;
+        while answers.len() < self.quorum {
+            let batch: Vec<&Endpoint> = remaining.by_ref().take(self.quorum - answers.len()).collect();
+            if batch.is_empty() {
+                return Err(last.expect("an endpoint failed"));
+            }
+            self.requests.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            let results = join_all(
+                batch
+                    .iter()
+                    .map(|endpoint| endpoint.send(self.next_id.fetch_add(1, Ordering::Relaxed), method, params)),
+            )
+            .await;
+            for (endpoint, result) in batch.into_iter().zip(results) {
+                match result {
+                    Ok(value) => answers.push((endpoint, value)),
+                    Err(failure) if failure.fails_over() => keep(&mut last, failure),
+                    Err(failure) => return Err(failure),
+                }
+            }
+        }
+
+        if answers.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+            let endpoints: Vec<&str> = answers.iter().map(|(endpoint, _)| endpoint.label.as_str()).collect();
+            return Err(RpcFailure::Disagreement { method: method.to_string(), endpoints: endpoints.join(", ") });
+        }
+        Ok(answers.swap_remove(0).1)
+    }
+}
+
+/// Keeps the failure to report once every endpoint has failed: a lagging node's error says more
+/// about the request than a fault of some other endpoint does.
+fn keep(last: &mut Option<RpcFailure>, failure: RpcFailure) {
+    let node_error_kept = last.as_ref().is_some_and(|kept| !kept.is_endpoint_fault());
+    if !(failure.is_endpoint_fault() && node_error_kept) {
+        *last = Some(failure);
+    }
+}
+
+/// Where requests go: the live endpoints, optionally recording every answer, or a recorded fixture.
+#[derive(Debug)]
+pub enum Transport {
+    Live { failover: Failover, recorder: Option<Recorder> },
+    Replay(Replay),
+}
+
+impl Transport {
+    /// Requests sent (or replayed) since startup
+    pub fn requests(&self) -> u64 {
+        match self {
+            Transport::Live { failover, .. } => failover.requests(),
+            Transport::Replay(replay) => replay.requests(),
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for Transport {
+    type Error = RpcFailure;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = serde_json::to_value(params).map_err(|err| RpcFailure::Decode { err, text: String::new() })?;
+        let result = match self {
+            Transport::Live { failover, recorder } => {
+                let result = failover.send(method, &params).await;
+                if let Some(recorder) = recorder {
+                    recorder.record(method, &params, &result);
+                }
+                result?
+            }
+            Transport::Replay(replay) => replay.answer(method, &params)?,
+        };
+        serde_json::from_value(result.clone()).map_err(|err| RpcFailure::Decode { err, text: result.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+
+    const LAGGING: &str = r#"{"code":-32000,"message":"header not found"}"#;
+    const REVERT: &str = r#"{"code":3,"message":"execution reverted: UniswapV2Library: INSUFFICIENT_LIQUIDITY","data":"0x08c379a0"}"#;
+
+    /// A local endpoint answering every request with `result`, or with the JSON-RPC `error`
+    async fn endpoint(answer: Result<&'static str, &'static str>) -> String {
+        let reply = match answer {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": 1, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": 1, "error": serde_json::from_str::<Value>(error).unwrap() }),
+        };
+        let app = Router::new().route("/", post(move || async move { Json(reply) }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    async fn failover(answers: Vec<Result<&'static str, &'static str>>, quorum: usize) -> Failover {
+        let mut endpoints = Vec::new();
+        for answer in answers {
+            endpoints.push(Endpoint::new(&endpoint(answer).await, reqwest::Client::new(), 4).unwrap());
+        }
+        Failover { endpoints, quorum, next_id: AtomicU64::new(1), requests: AtomicU64::new(0) }
+    }
+
+    #[tokio::test]
+    async fn a_lagging_node_fails_over_to_the_next_endpoint() {
+        let failover = failover(vec![Err(LAGGING), Ok("0x1")], 1).await;
+        assert_eq!(failover.send("eth_getBlockByNumber", &json!([])).await.unwrap(), json!("0x1"));
+        assert_eq!(failover.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_quorum_replaces_a_lagging_node() {
+        let failover = failover(vec![Err(LAGGING), Ok("0x1"), Ok("0x1")], 2).await;
+        assert_eq!(failover.send("eth_call", &json!([])).await.unwrap(), json!("0x1"));
+    }
+
+    #[tokio::test]
+    async fn the_lagging_node_error_is_returned_once_every_endpoint_has_failed() {
+        let failover = failover(vec![Err(LAGGING), Err(LAGGING)], 1).await;
+        match failover.send("eth_call", &json!([])).await {
+            Err(RpcFailure::JsonRpc(error)) => assert_eq!(error.message, "header not found"),
+            other => panic!("expected the node error, got {other:?}"),
+        }
+        assert_eq!(failover.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_revert_is_returned_from_the_first_endpoint() {
+        let failover = failover(vec![Err(REVERT), Ok("0x1")], 1).await;
+        match failover.send("eth_call", &json!([])).await {
+            Err(RpcFailure::JsonRpc(error)) => assert_eq!(error.code, 3),
+            other => panic!("expected the revert, got {other:?}"),
+        }
+        assert_eq!(failover.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_quorum_returns_a_revert_without_asking_further_endpoints() {
+        let failover = failover(vec![Err(REVERT), Ok("0x1"), Ok("0x1")], 2).await;
+        assert!(matches!(failover.send("eth_call", &json!([])).await, Err(RpcFailure::JsonRpc(_))));
+        assert_eq!(failover.requests(), 2);
+    }
+}
@@ -0,0 +1,245 @@
+use std::fs::{File, OpenOptions};
+use anyhow::{bail, Result};
+use csv::{ReaderBuilder, Writer, WriterBuilder};
+use crate::arbitrage::{Evaluation, Opportunity};
+use crate::web_monitor::{SharedEvaluations, SharedOpportunities, EVALUATION_HISTORY};
+
+/// One evaluated block: what it cost and what it found.
+#[derive(Debug, Clone)]
+pub struct BlockSummary {
+    pub block_number: u64,
+    pub block_hash: String,
+    /// UNIX seconds when the evaluation finished
+    pub timestamp: u64,
+    pub gas_gwei: f64,
+    pub evaluation_ms: u64,
+    pub rpc_requests: u64,
+    /// Opportunities recorded for the block
+    pub opportunities: usize,
+}
+
+/// Somewhere detected opportunities are recorded.
+pub trait Sink: Send {
+    fn record(&mut self, opportunity: &Opportunity) -> Result<()>;
+
+    /// Every (sampled) evaluation of a pair or cycle, profitable or not
+    fn evaluation(&mut self, _evaluation: &Evaluation) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once per evaluated block, after its opportunities
+    fn evaluated(&mut self, _block: &BlockSummary) -> Result<()> {
+        Ok(())
+    }
+
+    /// Persist anything buffered; called on shutdown
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Open `path` for appending rows with `header`, writing the header when the file is new. A file
+/// written with other columns (e.g. by an older version) is refused rather than mixed into.
+pub fn append_csv(path: &str, header: &[&str]) -> Result<Writer<File>> {
+    // Create if absent, append always
+    let file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    if !empty {
+        let existing = ReaderBuilder::new().has_headers(false).from_reader(&file).records().next().transpose()?;
+        if existing.as_ref().map(|record| record.iter().eq(header.iter().copied())) != Some(true) {
+            bail!("{path} has different columns than this version writes; move it aside or log to a new file");
+        }
+    }
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(file);
+    if empty {
+        writer.write_record(header)?;
+        writer.flush()?;
+    }
+    Ok(writer)
+}
+
+/// Columns of the CSV log, in order
+const CSV_HEADER: [&str; 21] = [
+    "id",
+    "timestamp",
+    "block",
+    "block_hash",
+    "pair",
+    "buy_venue",
+    "sell_venue",
+    "direction",
+    "path",
+    "amount_in",
+    "intermediate_amount",
+    "amount_out",
+    "quote",
+    "gross_profit",
+    "gas",
+    "net_profit",
+    "optimal_amount",
+    "optimal_profit",
+    "price_impact",
+    "slippage_profit",
+    "latency_ms",
+];
+
+/// Appends one row per opportunity to a CSV file, writing the header when the file is new.
+pub struct CsvSink {
+    writer: Writer<File>,
+}
+
+impl CsvSink {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(CsvSink { writer: append_csv(path, &CSV_HEADER)? })
+    }
+}
+
+impl Sink for CsvSink {
+    fn record(&mut self, o: &Opportunity) -> Result<()> {
+        let optional = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+        self.writer.write_record([
+            o.id.clone(),
+            o.timestamp.to_string(),
+            o.block_number.to_string(),
+            o.block_hash.clone(),
+            o.pair.clone(),
+            o.buy_venue.clone(),
+            o.sell_venue.clone(),
+            o.direction.clone(),
+            o.path.join(">"),
+            o.amount_in.to_string(),
+            o.intermediate_amount.to_string(),
+            o.amount_out.to_string(),
+            o.quote_symbol.clone(),
+            o.gross_profit.to_string(),
+            o.gas_cost.to_string(),
+            o.net_profit.to_string(),
+            optional(o.optimal_amount),
+            optional(o.optimal_profit),
+            o.price_impact.to_string(),
+            o.slippage_profit.to_string(),
+            o.latency_ms.to_string(),
+        ])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Columns of the evaluations CSV log, in order
+const EVALUATION_HEADER: [&str; 10] = [
+    "timestamp",
+    "block",
+    "pair",
+    "routes",
+    "best_direction",
+    "quote",
+    "best_profit",
+    "spread_pct",
+    "min_profit",
+    "profitable",
+];
+
+/// Appends one row per evaluation, profitable or not, to its own CSV file.
+pub struct EvaluationCsvSink {
+    writer: Writer<File>,
+}
+
+impl EvaluationCsvSink {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(EvaluationCsvSink { writer: append_csv(path, &EVALUATION_HEADER)? })
+    }
+}
+
+impl Sink for EvaluationCsvSink {
+    /// Opportunities go to the main CSV log
+    fn record(&mut self, _opportunity: &Opportunity) -> Result<()> {
+        Ok(())
+    }
+
+    fn evaluation(&mut self, e: &Evaluation) -> Result<()> {
+        let best = e.best();
+        self.writer.write_record([
+            e.timestamp.to_string(),
+            e.block_number.to_string(),
+            e.pair.clone(),
+            e.quotes.len().to_string(),
+            best.map(|best| best.direction.clone()).unwrap_or_default(),
+            best.map(|best| best.quote_symbol.clone()).unwrap_or_default(),
+            best.map(|best| best.net_profit.to_string()).unwrap_or_default(),
+            e.spread_pct().map(|spread| spread.to_string()).unwrap_or_default(),
+            e.min_profit.to_string(),
+            e.profitable().to_string(),
+        ])?;
+        Ok(())
+    }
+
+    fn evaluated(&mut self, _block: &BlockSummary) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Feeds the live dashboard.
+pub struct DashboardSink {
+    state: SharedOpportunities,
+    evaluations: SharedEvaluations,
+}
+
+impl DashboardSink {
+    pub fn new(state: SharedOpportunities, evaluations: SharedEvaluations) -> Self {
+        DashboardSink { state, evaluations }
+    }
+}
+
+impl Sink for DashboardSink {
+    fn record(&mut self, opportunity: &Opportunity) -> Result<()> {
+        self.state.lock().unwrap().push(opportunity.clone());
+        Ok(())
+    }
+
+    fn evaluation(&mut self, evaluation: &Evaluation) -> Result<()> {
+        let mut evaluations = self.evaluations.lock().unwrap();
+        if evaluations.len() == EVALUATION_HISTORY {
+            evaluations.pop_front();
+        }
+        evaluations.push_back(evaluation.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("arb-sinks-{}-{name}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn a_new_file_gets_the_header_once() {
+        let path = path("new");
+        append_csv(&path, &["a", "b"]).unwrap().write_record(["1", "2"]).unwrap();
+        append_csv(&path, &["a", "b"]).unwrap().write_record(["3", "4"]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a,b\n1,2\n3,4\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_file_with_other_columns_is_refused_and_left_alone() {
+        let path = path("old");
+        fs::write(&path, "timestamp,pair,profit\n1,WETH/USDC,2\n").unwrap();
+        assert!(CsvSink::open(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "timestamp,pair,profit\n1,WETH/USDC,2\n");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,379 @@
+use std::sync::{mpsc, Arc, Mutex};
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, Row};
+use serde::Deserialize;
+use crate::arbitrage::{Evaluation, LegQuote, Opportunity};
+use crate::executor::Execution;
+use crate::sinks::{BlockSummary, Sink};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS opportunities (
+    id TEXT PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    block_hash TEXT NOT NULL,
+    pair TEXT NOT NULL,
+    buy_venue TEXT NOT NULL,
+    sell_venue TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    path TEXT NOT NULL,
+    amount_in REAL NOT NULL,
+    intermediate_amount REAL NOT NULL,
+    amount_out REAL NOT NULL,
+    quote_symbol TEXT NOT NULL,
+    gross_profit REAL NOT NULL,
+    gas_cost REAL NOT NULL,
+    net_profit REAL NOT NULL,
+    optimal_amount REAL,
+    optimal_profit REAL,
+    price_impact REAL NOT NULL,
+    slippage_profit REAL NOT NULL,
+    robust INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS opportunities_by_time ON opportunities (timestamp);
+CREATE INDEX IF NOT EXISTS opportunities_by_pair ON opportunities (pair, timestamp);
+
+CREATE TABLE IF NOT EXISTS legs (
+    opportunity_id TEXT NOT NULL REFERENCES opportunities (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    venue TEXT NOT NULL,
+    token_in TEXT NOT NULL,
+    token_out TEXT NOT NULL,
+    amount_in REAL NOT NULL,
+    amount_out REAL NOT NULL,
+    mid_price REAL NOT NULL,
+    price_impact REAL NOT NULL,
+    PRIMARY KEY (opportunity_id, position)
+);
+CREATE INDEX IF NOT EXISTS legs_by_venue ON legs (venue, opportunity_id);
+
+CREATE TABLE IF NOT EXISTS evaluations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    pair TEXT NOT NULL,
+    routes INTEGER NOT NULL,
+    best_direction TEXT,
+    best_profit REAL,
+    spread_pct REAL,
+    min_profit REAL NOT NULL,
+    profitable INTEGER NOT NULL,
+    -- every priced route, best first, as JSON
+    quotes TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS evaluations_by_time ON evaluations (timestamp);
+CREATE INDEX IF NOT EXISTS evaluations_by_pair ON evaluations (pair, timestamp);
+
+CREATE TABLE IF NOT EXISTS blocks (
+    block_number INTEGER PRIMARY KEY,
+    block_hash TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    gas_gwei REAL NOT NULL,
+    evaluation_ms INTEGER NOT NULL,
+    rpc_requests INTEGER NOT NULL,
+    opportunities INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS executions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    opportunity_id TEXT NOT NULL,
+    pair TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    quote_symbol TEXT NOT NULL,
+    expected_profit REAL NOT NULL,
+    min_profit REAL,
+    status TEXT NOT NULL,
+    tx_hash TEXT,
+    nonce INTEGER,
+    block_number INTEGER,
+    gas_used INTEGER,
+    gas_cost REAL,
+    realised_profit REAL,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS executions_by_time ON executions (timestamp);
+";
+
+const OPPORTUNITY_COLUMNS: &str = "id, timestamp, block_number, block_hash, pair, buy_venue, sell_venue, direction, path, \
+    amount_in, intermediate_amount, amount_out, quote_symbol, gross_profit, gas_cost, net_profit, optimal_amount, \
+    optimal_profit, price_impact, slippage_profit, robust, latency_ms";
+
+/// Which stored opportunities to return; unset fields match everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct Filter {
+    pub pair: Option<String>,
+    /// Matches any leg, not only the buy and sell venue
+    pub venue: Option<String>,
+    /// UNIX seconds, inclusive
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    /// Most recent first; defaults to `DEFAULT_LIMIT`
+    pub limit: Option<u32>,
+}
+
+const DEFAULT_LIMIT: u32 = 1000;
+
+/// Embedded SQLite history of every evaluated block, every (sampled) evaluation, every opportunity
+/// with its leg quotes and every execution attempt.
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    /// Open (or create) the database at `path` and make sure the schema exists.
+    pub fn open(path: &str) -> Result<Arc<Self>> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Arc::new(Store { conn: Mutex::new(conn) }))
+    }
+
+    pub fn insert_opportunity(&self, o: &Opportunity) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            &format!("INSERT OR REPLACE INTO opportunities ({OPPORTUNITY_COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)"),
+            params![
+                o.id,
+                o.timestamp,
+                o.block_number,
+                o.block_hash,
+                o.pair,
+                o.buy_venue,
+                o.sell_venue,
+                o.direction,
+                o.path.join(">"),
+                o.amount_in,
+                o.intermediate_amount,
+                o.amount_out,
+                o.quote_symbol,
+                o.gross_profit,
+                o.gas_cost,
+                o.net_profit,
+                o.optimal_amount,
+                o.optimal_profit,
+                o.price_impact,
+                o.slippage_profit,
+                o.robust,
+                o.latency_ms,
+            ],
+        )?;
+        tx.execute("DELETE FROM legs WHERE opportunity_id = ?1", [&o.id])?;
+        for (position, leg) in o.legs.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO legs (opportunity_id, position, venue, token_in, token_out, amount_in, amount_out, mid_price, price_impact) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![o.id, position, leg.venue, leg.token_in, leg.token_out, leg.amount_in, leg.amount_out, leg.mid_price, leg.price_impact],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn insert_evaluation(&self, e: &Evaluation) -> Result<()> {
+        let best = e.best();
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO evaluations (timestamp, block_number, pair, routes, best_direction, best_profit, spread_pct, min_profit, profitable, quotes) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                e.timestamp,
+                e.block_number,
+                e.pair,
+                e.quotes.len(),
+                best.map(|best| &best.direction),
+                best.map(|best| best.net_profit),
+                e.spread_pct(),
+                e.min_profit,
+                e.profitable(),
+                serde_json::to_string(&e.quotes)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The `limit` most recent evaluations, oldest first.
+    pub fn recent_evaluations(&self, limit: usize) -> Result<Vec<Evaluation>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT timestamp, block_number, pair, min_profit, quotes FROM evaluations ORDER BY id DESC LIMIT ?1",
+        )?;
+        let rows = query.query_map([limit], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get::<_, String>(4)?))
+        })?;
+        let mut evaluations = Vec::new();
+        for row in rows {
+            let (timestamp, block_number, pair, min_profit, quotes) = row?;
+            evaluations.push(Evaluation { timestamp, block_number, pair, min_profit, quotes: serde_json::from_str(&quotes)? });
+        }
+        evaluations.reverse();
+        Ok(evaluations)
+    }
+
+    pub fn insert_block(&self, b: &BlockSummary) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO blocks (block_number, block_hash, timestamp, gas_gwei, evaluation_ms, rpc_requests, opportunities) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![b.block_number, b.block_hash, b.timestamp, b.gas_gwei, b.evaluation_ms, b.rpc_requests, b.opportunities],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_execution(&self, e: &Execution) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO executions (timestamp, opportunity_id, pair, direction, quote_symbol, expected_profit, min_profit, \
+             status, tx_hash, nonce, block_number, gas_used, gas_cost, realised_profit, error) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            params![
+                e.timestamp,
+                e.opportunity_id,
+                e.pair,
+                e.direction,
+                e.quote_symbol,
+                e.expected_profit,
+                e.min_profit,
+                e.status.as_str(),
+                e.tx_hash,
+                e.nonce,
+                e.block_number,
+                e.gas_used,
+                e.gas_cost,
+                e.realised_profit,
+                e.error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Opportunities matching `filter`, most recent first, with their legs.
+    pub fn opportunities(&self, filter: &Filter) -> Result<Vec<Opportunity>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(&format!(
+            "SELECT {OPPORTUNITY_COLUMNS} FROM opportunities
+             WHERE (?1 IS NULL OR pair = ?1)
+               AND (?2 IS NULL OR id IN (SELECT opportunity_id FROM legs WHERE venue = ?2))
+               AND (?3 IS NULL OR timestamp >= ?3)
+               AND (?4 IS NULL OR timestamp <= ?4)
+             ORDER BY timestamp DESC, id
+             LIMIT ?5"
+        ))?;
+        let limit = filter.limit.unwrap_or(DEFAULT_LIMIT);
+        let mut opportunities = query
+            .query_map(params![filter.pair, filter.venue, filter.from, filter.to, limit], opportunity)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut legs = conn.prepare(
+            "SELECT venue, token_in, token_out, amount_in, amount_out, mid_price, price_impact \
+             FROM legs WHERE opportunity_id = ?1 ORDER BY position",
+        )?;
+        for o in &mut opportunities {
+            o.legs = legs.query_map([&o.id], leg)?.collect::<rusqlite::Result<Vec<_>>>()?;
+        }
+        Ok(opportunities)
+    }
+}
+
+fn opportunity(row: &Row) -> rusqlite::Result<Opportunity> {
+    Ok(Opportunity {
+        id: row.get(0)?,
+        timestamp: row.get(1)?,
+        block_number: row.get(2)?,
+        block_hash: row.get(3)?,
+        pair: row.get(4)?,
+        buy_venue: row.get(5)?,
+        sell_venue: row.get(6)?,
+        direction: row.get(7)?,
+        path: row.get::<_, String>(8)?.split('>').map(str::to_string).collect(),
+        amount_in: row.get(9)?,
+        intermediate_amount: row.get(10)?,
+        amount_out: row.get(11)?,
+        quote_symbol: row.get(12)?,
+        gross_profit: row.get(13)?,
+        gas_cost: row.get(14)?,
+        net_profit: row.get(15)?,
+        optimal_amount: row.get(16)?,
+        optimal_profit: row.get(17)?,
+        price_impact: row.get(18)?,
+        slippage_profit: row.get(19)?,
+        robust: row.get(20)?,
+        latency_ms: row.get(21)?,
+        legs: Vec::new(),
+        exact: None,
+    })
+}
+
+fn leg(row: &Row) -> rusqlite::Result<LegQuote> {
+    Ok(LegQuote {
+        venue: row.get(0)?,
+        token_in: row.get(1)?,
+        token_out: row.get(2)?,
+        amount_in: row.get(3)?,
+        amount_out: row.get(4)?,
+        mid_price: row.get(5)?,
+        price_impact: row.get(6)?,
+    })
+}
+
+/// A write queued for the writer thread.
+enum Write {
+    Opportunity(Box<Opportunity>),
+    Evaluation(Evaluation),
+    Block(BlockSummary),
+    /// Answered once every earlier write is done
+    Flush(mpsc::Sender<()>),
+}
+
+/// Persists opportunities, evaluations and block summaries to a `Store` from a dedicated writer
+/// thread, so SQLite never blocks the async runtime. Failed writes are logged.
+pub struct SqliteSink {
+    writes: mpsc::Sender<Write>,
+}
+
+impl SqliteSink {
+    pub fn new(store: Arc<Store>) -> Self {
+        let (writes, queue) = mpsc::channel();
+        std::thread::spawn(move || {
+            for write in queue {
+                let (what, result) = match write {
+                    Write::Opportunity(o) => (format!("opportunity {}", o.id), store.insert_opportunity(&o)),
+                    Write::Evaluation(e) => (format!("evaluation of {}", e.pair), store.insert_evaluation(&e)),
+                    Write::Block(b) => (format!("block {}", b.block_number), store.insert_block(&b)),
+                    Write::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                if let Err(e) = result {
+                    eprintln!("Failed to store {what}: {:?}", e);
+                }
+            }
+        });
+        SqliteSink { writes }
+    }
+
+    fn queue(&self, write: Write) -> Result<()> {
+        self.writes.send(write).map_err(|_| anyhow!("the SQLite writer has stopped"))
+    }
+}
+
+impl Sink for SqliteSink {
+    fn record(&mut self, opportunity: &Opportunity) -> Result<()> {
+        self.queue(Write::Opportunity(Box::new(opportunity.clone())))
+    }
+
+    fn evaluation(&mut self, evaluation: &Evaluation) -> Result<()> {
+        self.queue(Write::Evaluation(evaluation.clone()))
+    }
+
+    fn evaluated(&mut self, block: &BlockSummary) -> Result<()> {
+        self.queue(Write::Block(block.clone()))
+    }
+
+    /// Waits for every queued write
+    fn flush(&mut self) -> Result<()> {
+        let (done, flushed) = mpsc::channel();
+        self.queue(Write::Flush(done))?;
+        flushed.recv().map_err(|_| anyhow!("the SQLite writer has stopped"))
+    }
+}
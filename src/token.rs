@@ -0,0 +1,225 @@
+use ethers::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, bail, Result};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use crate::rpc::Client;
+
+// Abigen for the ERC-20 metadata we need
+abigen!(
+    Erc20,
+    r#"[
+        function decimals() external view returns (uint8)
+        function symbol() external view returns (string)
+    ]"#
+);
+
+/// Largest scale a `Decimal` can hold
+const MAX_DECIMAL_SCALE: u8 = 28;
+/// Most decimals a token can have: 10^78 no longer fits in a `U256`
+const MAX_DECIMALS: u8 = 77;
+
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl TokenInfo {
+    /// Human amount (like 1000.23) -> base units, rounding down
+    pub fn to_base_units(&self, amount: Decimal) -> Result<U256> {
+        to_base_units(amount, self.decimals)
+    }
+
+    /// Base units -> human amount
+    pub fn to_human(&self, value: U256) -> Result<Decimal> {
+        from_base_units(value, self.decimals)
+    }
+}
+
+/// Token metadata fetched once per address via `decimals()` / `symbol()` and cached.
+pub struct TokenRegistry {
+    provider: Arc<Client>,
+    cache: Mutex<HashMap<Address, TokenInfo>>,
+}
+
+impl TokenRegistry {
+    pub fn new(provider: Arc<Client>) -> Self {
+        TokenRegistry { provider, cache: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn get(&self, address: Address) -> Result<TokenInfo> {
+        if let Some(info) = self.cache.lock().unwrap().get(&address) {
+            return Ok(info.clone());
+        }
+
+        let token = Erc20::new(address, self.provider.clone());
+        let (decimals, symbol) = (token.decimals(), token.symbol());
+        let (decimals, symbol) = tokio::try_join!(decimals.call(), symbol.call())?;
+        // Read from an arbitrary contract: refuse what no amount of it could be converted with
+        if decimals > MAX_DECIMALS {
+            bail!("{symbol} ({address:?}) reports {decimals} decimals, more than the {MAX_DECIMALS} a U256 can scale");
+        }
+        let info = TokenInfo { symbol, decimals };
+
+        self.cache.lock().unwrap().insert(address, info.clone());
+        Ok(info)
+    }
+
+    /// Use known metadata for `address` instead of fetching it.
+    #[cfg(test)]
+    pub fn insert(&self, address: Address, info: TokenInfo) {
+        self.cache.lock().unwrap().insert(address, info);
+    }
+}
+
+/// Convert a human amount to base units for a token with `decimals` decimals, rounding down.
+/// The integer and fractional parts are scaled separately so 18-decimal tokens cannot overflow `Decimal`.
+pub fn to_base_units(amount: Decimal, decimals: u8) -> Result<U256> {
+    if amount.is_sign_negative() {
+        bail!("cannot convert negative amount {amount} to base units");
+    }
+    let unit = unit(decimals)?;
+
+    let whole = amount
+        .trunc()
+        .to_u128()
+        .ok_or_else(|| anyhow!("amount {amount} does not fit in u128"))?;
+    let whole = U256::from(whole)
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow!("amount {amount} overflows U256"))?;
+
+    // fract < 1, so fract * 10^28 always fits; any remaining decimals are plain zeros
+    let frac_scale = decimals.min(MAX_DECIMAL_SCALE);
+    let frac = (amount.fract() * Decimal::from_i128_with_scale(10i128.pow(frac_scale as u32), 0))
+        .trunc()
+        .to_u128()
+        .unwrap_or(0);
+    let frac = U256::from(frac) * U256::exp10((decimals - frac_scale) as usize);
+
+    whole
+        .checked_add(frac)
+        .ok_or_else(|| anyhow!("amount {amount} overflows U256"))
+}
+
+/// Convert base units of a token with `decimals` decimals to a human amount.
+/// Errors instead of panicking when the whole part is too large for `Decimal`; digits beyond
+/// `Decimal`'s 28-digit scale are truncated.
+pub fn from_base_units(value: U256, decimals: u8) -> Result<Decimal> {
+    let unit = unit(decimals)?;
+    let (whole, frac) = value.div_mod(unit);
+
+    // Decimal tops out around 7.9e28 (96-bit mantissa)
+    let whole = if whole > U256::from(u128::MAX) {
+        None
+    } else {
+        i128::try_from(whole.as_u128())
+            .ok()
+            .and_then(|whole| Decimal::try_from_i128_with_scale(whole, 0).ok())
+    }
+    .ok_or_else(|| anyhow!("{value} is too large to represent with {decimals} decimals"))?;
+
+    let frac_scale = decimals.min(MAX_DECIMAL_SCALE);
+    let frac = frac / U256::exp10((decimals - frac_scale) as usize);
+    let frac = Decimal::from_i128_with_scale(frac.as_u128() as i128, frac_scale as u32);
+
+    whole
+        .checked_add(frac)
+        .ok_or_else(|| anyhow!("{value} is too large to represent with {decimals} decimals"))
+}
+
+/// 10^decimals: one whole token in base units. Below it every smaller power fits too.
+fn unit(decimals: u8) -> Result<U256> {
+    if decimals > MAX_DECIMALS {
+        bail!("{decimals} decimals overflow U256");
+    }
+    Ok(U256::exp10(decimals as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn d(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    fn u(value: &str) -> U256 {
+        U256::from_dec_str(value).unwrap()
+    }
+
+    #[test]
+    fn digits_beyond_the_token_decimals_are_rounded_down() {
+        assert_eq!(to_base_units(d("1.2345679"), 6).unwrap(), u("1234567"));
+        assert_eq!(to_base_units(d("0.0000009"), 6).unwrap(), U256::zero());
+        assert_eq!(to_base_units(d("7.9"), 0).unwrap(), u("7"));
+    }
+
+    #[test]
+    fn large_amounts_of_18_decimal_tokens_do_not_overflow() {
+        // 10^11 whole tokens would overflow Decimal if scaled in one go
+        assert_eq!(to_base_units(d("100000000000.5"), 18).unwrap(), u("100000000000500000000000000000"));
+        assert_eq!(to_base_units(d("0.000000000000000001"), 18).unwrap(), U256::one());
+        // More decimals than Decimal can hold: the fraction is padded with zeros
+        assert_eq!(to_base_units(d("1.5"), 30).unwrap(), u("1500000000000000000000000000000"));
+    }
+
+    #[test]
+    fn negative_amounts_are_rejected() {
+        assert!(to_base_units(d("-0.01"), 6).is_err());
+    }
+
+    #[test]
+    fn base_units_round_trip() {
+        for (value, decimals, human) in [("1234567", 6, "1.234567"), ("1000000000000000001", 18, "1.000000000000000001"), ("42", 0, "42")] {
+            assert_eq!(from_base_units(u(value), decimals).unwrap(), d(human));
+            assert_eq!(to_base_units(d(human), decimals).unwrap(), u(value));
+        }
+        assert_eq!(from_base_units(U256::zero(), 18).unwrap(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn digits_beyond_decimal_precision_are_truncated() {
+        // 30 decimals: the last two digits do not fit Decimal's 28-digit scale
+        assert_eq!(from_base_units(u("1999999999999999999999999999999"), 30).unwrap(), d("1.9999999999999999999999999999"));
+    }
+
+    #[test]
+    fn values_too_large_for_decimal_are_errors_not_panics() {
+        assert!(from_base_units(U256::MAX, 0).is_err());
+        assert!(from_base_units(U256::MAX, 18).is_err());
+        assert!(from_base_units(u("79228162514264337593543950335"), 0).is_ok());
+        assert!(from_base_units(u("79228162514264337593543950336"), 0).is_err());
+    }
+
+    #[test]
+    fn decimals_beyond_u256_are_errors_not_panics() {
+        assert_eq!(to_base_units(d("1"), 77).unwrap(), U256::exp10(77));
+        assert_eq!(from_base_units(U256::exp10(77), 77).unwrap(), d("1"));
+        for decimals in [78, u8::MAX] {
+            assert!(to_base_units(d("1"), decimals).is_err());
+            assert!(from_base_units(U256::one(), decimals).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn tokens_reporting_too_many_decimals_are_not_registered() {
+        let path = std::env::temp_dir().join(format!("arb-token-{}.jsonl", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+        let token = Address::repeat_byte(0xbd);
+        let call = |selector: &str| serde_json::json!([{ "to": token, "data": selector }, "latest"]);
+        let recorder = crate::fixture::Recorder::create(&path).unwrap();
+        let answer = |value: ethers::abi::Token| Ok(serde_json::json!(Bytes::from(ethers::abi::encode(&[value]))));
+        recorder.record("eth_call", &call("0x313ce567"), &answer(ethers::abi::Token::Uint(78.into())));
+        recorder.record("eth_call", &call("0x95d89b41"), &answer(ethers::abi::Token::String("BAD".to_string())));
+        drop(recorder);
+
+        let tokens = TokenRegistry::new(Arc::new(crate::rpc::replay(&path).unwrap()));
+        let error = tokens.get(token).await.unwrap_err().to_string();
+        assert!(error.contains("78 decimals"), "{error}");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+use anyhow::{anyhow, Context, Result};
+use ethers::types::{Address, BlockId, U256};
+use futures::future::join_all;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use crate::blocks::Head;
+use crate::config::CycleConfig;
+use crate::dex::{Dex, DexKind};
+use crate::gas::GasCosts;
+use crate::token::{TokenInfo, TokenRegistry};
+
+/// Fraction of a leg's input quoted to read the pool's marginal price
+const PROBE_DIVISOR: u64 = 10_000;
+
+/// One swap inside a cycle or route, amounts in human units and prices in `token_out` per `token_in`.
+#[derive(Debug, Clone)]
+pub struct Leg {
+    pub venue: String,
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+    /// Pool price before fees and size effects
+    pub mid_price: Decimal,
+    /// Fraction of the output lost to the trade's own size, fees excluded
+    pub price_impact: Decimal,
+}
+
+impl Leg {
+    /// Build a leg from base-unit `(amount_in, amount_out)` and a `probe` quote `(probe_amount(amount_in), out)`
+    /// on the same venue and block, which reads the marginal price the trade starts at.
+    pub fn new(
+        venue: &dyn Dex,
+        token_in: &TokenInfo,
+        token_out: &TokenInfo,
+        amounts: (U256, U256),
+        probe: (U256, U256),
+    ) -> Result<Leg> {
+        let amount_in = token_in.to_human(amounts.0)?;
+        let amount_out = token_out.to_human(amounts.1)?;
+        // Marginal price after fees: what an infinitely small trade would get
+        let spot = token_out
+            .to_human(probe.1)?
+            .checked_div(token_in.to_human(probe.0)?)
+            .filter(|spot| !spot.is_zero())
+            .ok_or_else(|| anyhow!("{} {}->{} has no price", venue.name(), token_in.symbol, token_out.symbol))?;
+        let mid_price = match venue.fee() {
+            Some(fee) => spot / (Decimal::ONE - Decimal::from(fee) / Decimal::from(1_000_000)),
+            None => spot,
+        };
+        let mut leg = Leg {
+            venue: venue.name().to_string(),
+            token_in: token_in.symbol.clone(),
+            token_out: token_out.symbol.clone(),
+            amount_in,
+            amount_out,
+            mid_price,
+            price_impact: Decimal::ZERO,
+        };
+        leg.price_impact = Decimal::ONE - leg.execution_price() / spot;
+        Ok(leg)
+    }
+
+    /// Output per unit of input actually received
+    pub fn execution_price(&self) -> Decimal {
+        self.amount_out.checked_div(self.amount_in).unwrap_or_default()
+    }
+}
+
+/// Input for the marginal-price quote of a leg trading `amount_in`.
+pub fn probe_amount(amount_in: U256) -> U256 {
+    (amount_in / PROBE_DIVISOR).max(U256::one())
+}
+
+/// Profit if the final output only reaches the minimum-out a `slippage_bps` tolerance allows,
+/// i.e. after an adverse move the transaction would still accept.
+pub fn profit_at_slippage(legs: &[Leg], gas_cost: Decimal, slippage_bps: u32) -> Decimal {
+    let (Some(first), Some(last)) = (legs.first(), legs.last()) else {
+        return Decimal::ZERO;
+    };
+    let min_out = last.amount_out * (Decimal::ONE - Decimal::from(slippage_bps) / Decimal::from(10_000));
+    min_out - first.amount_in - gas_cost
+}
+
+/// A priced cycle: its legs and the net profit in the start token.
+#[derive(Debug, Clone)]
+pub struct CycleResult {
+    pub cycle: String,
+    pub legs: Vec<Leg>,
+    /// Symbol of the start/end token `profit` is denominated in
+    pub quote_symbol: String,
+    pub profit: Decimal,
+    /// Gas cost in the start token already subtracted from `profit`
+    pub gas_cost: Decimal,
+    /// Block every quote of the cycle was pinned to
+    pub block: Head,
+}
+
+impl CycleResult {
+    /// Venues visited in order, e.g. `QuickSwap>QuickSwap>SushiSwap`
+    pub fn route(&self) -> String {
+        self.legs.iter().map(|leg| leg.venue.as_str()).collect::<Vec<_>>().join(">")
+    }
+}
+
+/// Price `cycle` (e.g. USDC>WETH>WMATIC>USDC) starting with `cycle.trade_amount` of its first token:
+/// - entirely on each venue, as one multi-hop quote
+/// - across venues, taking the venue with the best output for each leg in turn
+///
+/// Profit = end_amount - trade_amount - gas, with gas priced for the venues used. Every quote is
+/// pinned to `head`. Results are ranked best first; venues whose quotes fail are logged and skipped.
+pub async fn evaluate_cycle(
+    venues: &[Arc<dyn Dex>],
+    tokens: &TokenRegistry,
+    cycle: &CycleConfig,
+    gas: &GasCosts,
+    head: Head,
+) -> Result<Vec<CycleResult>> {
+    let start = tokens.get(cycle.path[0]).await?;
+    let trade_amount = Decimal::from_f64(cycle.trade_amount)
+        .ok_or_else(|| anyhow!("{}: trade amount {} is not representable", cycle.name, cycle.trade_amount))?;
+    let amount_in = start.to_base_units(trade_amount)?;
+    let block = head.id();
+
+    // Single venue (the whole path in one quote) and cross venue (best venue per leg) at once
+    let (whole, cross) = tokio::join!(
+        join_all(venues.iter().map(|venue| venue.quote_hops(amount_in, &cycle.path, block))),
+        best_per_leg(venues, &cycle.name, &cycle.path, amount_in, block),
+    );
+
+    let mut routes: Vec<(Vec<&dyn Dex>, Vec<U256>)> = Vec::new();
+    for (venue, quoted) in venues.iter().zip(whole) {
+        match quoted {
+            Ok(amounts) => routes.push((vec![venue.as_ref(); cycle.path.len() - 1], amounts)),
+            Err(e) => eprintln!("{} on {} failed: {:?}", cycle.name, venue.name(), e),
+        }
+    }
+    // Only worth reporting if it actually mixes venues; otherwise it duplicates a single-venue result
+    if let Some((hops, amounts)) = cross {
+        if hops.iter().any(|venue| venue.name() != hops[0].name()) {
+            routes.push((hops, amounts));
+        }
+    }
+
+    let priced = join_all(
+        routes
+            .iter()
+            .map(|(hops, amounts)| price_cycle(tokens, &cycle.name, &cycle.path, hops, amounts, gas, head)),
+    )
+    .await;
+    // A route whose legs cannot be priced (e.g. a failed probe) is dropped, not the whole cycle
+    let mut results: Vec<CycleResult> = routes
+        .iter()
+        .zip(priced)
+        .filter_map(|((hops, _), result)| {
+            result
+                .inspect_err(|e| {
+                    let route: Vec<&str> = hops.iter().map(|venue| venue.name()).collect();
+                    eprintln!("{} via {} skipped: {:?}", cycle.name, route.join(">"), e);
+                })
+                .ok()
+        })
+        .collect();
+    results.sort_by_key(|result| std::cmp::Reverse(result.profit));
+    Ok(results)
+}
+
+/// Walk `path` taking the venue with the best output for each leg in turn. Returns the venue and
+/// the base-unit amount after each leg, or `None` when some leg has no working venue.
+async fn best_per_leg<'a>(
+    venues: &'a [Arc<dyn Dex>],
+    name: &str,
+    path: &[Address],
+    amount_in: U256,
+    block: BlockId,
+) -> Option<(Vec<&'a dyn Dex>, Vec<U256>)> {
+    let mut amounts = vec![amount_in];
+    let mut hops: Vec<&dyn Dex> = Vec::new();
+    for hop in path.windows(2) {
+        let amount = *amounts.last().unwrap();
+        let mut best: Option<(U256, &dyn Dex)> = None;
+        let quotes = join_all(venues.iter().map(|venue| venue.quote(amount, hop, block))).await;
+        for (venue, quoted) in venues.iter().zip(quotes) {
+            match quoted {
+                Ok(out) if best.is_none_or(|(best_out, _)| out > best_out) => best = Some((out, venue.as_ref())),
+                Ok(_) => {}
+                Err(e) => eprintln!("{} leg on {} failed: {:?}", name, venue.name(), e),
+            }
+        }
+        let (out, venue) = best?;
+        amounts.push(out);
+        hops.push(venue);
+    }
+    Some((hops, amounts))
+}
+
+/// Simulate `path` with a fixed venue per leg (`hops[i]` swaps `path[i]` -> `path[i + 1]`),
+/// starting with `trade_amount` of `path[0]`, at `head`. Used to price cycles found by `graph`.
+pub async fn simulate_route(
+    tokens: &TokenRegistry,
+    name: &str,
+    path: &[Address],
+    hops: &[Arc<dyn Dex>],
+    trade_amount: Decimal,
+    gas: &GasCosts,
+    head: Head,
+) -> Result<CycleResult> {
+    let start = tokens.get(path[0]).await?;
+    let mut amounts = vec![start.to_base_units(trade_amount)?];
+    for (venue, hop) in hops.iter().zip(path.windows(2)) {
+        let amount = *amounts.last().unwrap();
+        amounts.push(venue.quote(amount, hop, head.id()).await?);
+    }
+    let hops: Vec<&dyn Dex> = hops.iter().map(|venue| venue.as_ref()).collect();
+    price_cycle(tokens, name, path, &hops, &amounts, gas, head).await
+}
+
+/// Turn per-hop base-unit amounts into legs and a net profit. Each leg's marginal price is read
+/// with one probe quote on its venue at the same block.
+async fn price_cycle(
+    tokens: &TokenRegistry,
+    name: &str,
+    path: &[Address],
+    hops: &[&dyn Dex],
+    amounts: &[U256],
+    gas: &GasCosts,
+    head: Head,
+) -> Result<CycleResult> {
+    let probes = join_all(
+        hops.iter()
+            .zip(path.windows(2))
+            .zip(amounts)
+            .map(|((venue, hop), amount)| venue.quote(probe_amount(*amount), hop, head.id())),
+    )
+    .await;
+
+    let mut legs = Vec::with_capacity(hops.len());
+    for (i, (venue, probe)) in hops.iter().zip(probes).enumerate() {
+        let token_in = tokens.get(path[i]).await?;
+        let token_out = tokens.get(path[i + 1]).await?;
+        let probe = (probe_amount(amounts[i]), probe.with_context(|| format!("{} mid price probe failed", venue.name()))?);
+        legs.push(Leg::new(*venue, &token_in, &token_out, (amounts[i], amounts[i + 1]), probe)?);
+    }
+
+    let (start, end) = match (legs.first(), legs.last()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Err(anyhow!("{name} has no legs")),
+    };
+    let kinds: Vec<DexKind> = hops.iter().map(|venue| venue.kind()).collect();
+    let gas_cost = gas.cost(path[0], &kinds);
+    let profit = end.amount_out - start.amount_in - gas_cost;
+    Ok(CycleResult {
+        cycle: name.to_string(),
+        quote_symbol: end.token_out.clone(),
+        legs,
+        profit,
+        gas_cost,
+        block: head,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GasConfig;
+    use crate::mock::{self, MockDex};
+
+    fn usdc() -> Address {
+        Address::repeat_byte(0xcc)
+    }
+
+    fn weth() -> Address {
+        Address::repeat_byte(0xee)
+    }
+
+    fn wmatic() -> Address {
+        Address::repeat_byte(0x77)
+    }
+
+    /// Venue with USDC/WETH, WETH/WMATIC and WMATIC/USDC pools, its first one holding `weth_reserve`
+    /// base units of WETH against 2,000,000 USDC
+    fn venue(name: &str, weth_reserve: U256) -> Arc<dyn Dex> {
+        Arc::new(
+            MockDex::new(name, 3000)
+                .with_pool(usdc(), weth(), mock::units(2_000_000, 6), weth_reserve)
+                .with_pool(weth(), wmatic(), mock::units(1000, 18), mock::units(4_000_000, 18))
+                .with_pool(wmatic(), usdc(), mock::units(4_000_000, 18), mock::units(2_000_000, 6)),
+        )
+    }
+
+    #[tokio::test]
+    async fn a_route_without_a_mid_price_only_drops_that_route() {
+        // So little WETH that the first leg's probe rounds down to nothing
+        let venues = [venue("Deep", mock::units(1000, 18)), venue("Thin", U256::exp10(6)), venue("Other", mock::units(1000, 18))];
+        let tokens = mock::tokens(&[(usdc(), "USDC", 6), (weth(), "WETH", 18), (wmatic(), "WMATIC", 18)]);
+        let cycle = CycleConfig {
+            name: "USDC>WETH>WMATIC>USDC".to_string(),
+            path: vec![usdc(), weth(), wmatic(), usdc()],
+            trade_amount: 1000.0,
+            min_profit: 5.0,
+        };
+        let gas = GasCosts::fixed(&GasConfig { wrapped_native: Address::zero(), price_venue: None, flash_loan: false, fallback: 0.5 });
+
+        let results = evaluate_cycle(&venues, &tokens, &cycle, &gas, mock::head(100)).await.unwrap();
+        let routes: Vec<String> = results.iter().map(CycleResult::route).collect();
+        assert!(routes.contains(&"Deep>Deep>Deep".to_string()) && routes.contains(&"Other>Other>Other".to_string()), "{routes:?}");
+        assert!(routes.iter().all(|route| !route.contains("Thin")), "{routes:?}");
+    }
+}
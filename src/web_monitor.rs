@@ -1,26 +1,113 @@
 use axum::{
+    extract::Query,
+    http::StatusCode,
     routing::get,
     Router,
     Json,
     response::Html,
 };
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::{Arc, Mutex};
 use serde::Serialize;
+use crate::arbitrage::{Evaluation, Opportunity};
+use crate::errors::{ClassCount, ErrorStats};
+use crate::store::{Filter, Store};
+
+/// Opportunities recorded so far, served to the dashboard
+pub type SharedOpportunities = Arc<Mutex<Vec<Opportunity>>>;
+
+/// Most recent evaluations, profitable or not, for the spread histogram
+pub type SharedEvaluations = Arc<Mutex<VecDeque<Evaluation>>>;
+
+/// Evaluations kept in memory for the dashboard
+pub const EVALUATION_HISTORY: usize = 10_000;
+
+/// Upper edges (percent) of the spread histogram bins; one more bin holds everything above the last
+const SPREAD_EDGES: [f64; 9] = [-1.0, -0.5, -0.25, -0.1, 0.0, 0.1, 0.25, 0.5, 1.0];
+
+/// A near miss made at least this fraction of its minimum profit
+const NEAR_MISS: f64 = 0.5;
+
+/// How often spreads exist and how close they come to the threshold, over the recent evaluations.
+#[derive(Debug, Serialize)]
+pub struct SpreadStats {
+    pub evaluations: usize,
+    /// Evaluations where every venue failed
+    pub failed: usize,
+    /// Best route returned more than it took in, before gas
+    pub positive_spread: usize,
+    pub profitable: usize,
+    /// Not profitable, but the best net profit reached `NEAR_MISS` of the minimum
+    pub near_misses: usize,
+    /// Highest best net profit / minimum profit among the unprofitable evaluations
+    pub closest_to_threshold: Option<f64>,
+    /// Gross spread of the best route, in percent: `(label, count)`
+    pub histogram: Vec<(String, usize)>,
+}
 
-#[derive(Clone, Serialize)]
-pub struct ArbData {
-    pub timestamp: u64,
-    pub direction: String,
-    pub profit_usdc: f64,
+pub fn spread_stats(evaluations: &VecDeque<Evaluation>) -> SpreadStats {
+    let mut histogram: Vec<(String, usize)> = SPREAD_EDGES
+        .iter()
+        .map(|edge| (format!("≤{edge}%"), 0))
+        .chain(std::iter::once((format!(">{}%", SPREAD_EDGES[SPREAD_EDGES.len() - 1]), 0)))
+        .collect();
+    let mut stats = SpreadStats {
+        evaluations: evaluations.len(),
+        failed: 0,
+        positive_spread: 0,
+        profitable: 0,
+        near_misses: 0,
+        closest_to_threshold: None,
+        histogram: Vec::new(),
+    };
+    for evaluation in evaluations {
+        let (Some(best), Some(spread)) = (evaluation.best(), evaluation.spread_pct()) else {
+            stats.failed += 1;
+            continue;
+        };
+        histogram[SPREAD_EDGES.iter().position(|edge| spread <= *edge).unwrap_or(SPREAD_EDGES.len())].1 += 1;
+        if spread > 0.0 {
+            stats.positive_spread += 1;
+        }
+        if evaluation.profitable() {
+            stats.profitable += 1;
+        } else if evaluation.min_profit > 0.0 {
+            let reached = best.net_profit / evaluation.min_profit;
+            if reached >= NEAR_MISS {
+                stats.near_misses += 1;
+            }
+            stats.closest_to_threshold = Some(stats.closest_to_threshold.map_or(reached, |closest| closest.max(reached)));
+        }
+    }
+    stats.histogram = histogram;
+    stats
 }
 
-pub type SharedArbData = Arc<Mutex<Vec<ArbData>>>;
+pub async fn get_spread_stats(evaluations: SharedEvaluations) -> Json<SpreadStats> {
+    Json(spread_stats(&evaluations.lock().unwrap()))
+}
 
-pub async fn get_arb_data(state: SharedArbData) -> Json<Vec<ArbData>> {
+pub async fn get_arb_data(state: SharedOpportunities) -> Json<Vec<Opportunity>> {
     let data = state.lock().unwrap();
     Json(data.clone())
 }
 
+/// Stored opportunities by `pair`, `venue` and `from`/`to` (UNIX seconds), most recent first
+pub async fn query_opportunities(store: Option<Arc<Store>>, filter: Filter) -> Result<Json<Vec<Opportunity>>, (StatusCode, String)> {
+    let store = store.ok_or((StatusCode::NOT_FOUND, "no database configured (DB_PATH)".to_string()))?;
+    // SQLite blocks; keep it off the runtime's worker threads
+    tokio::task::spawn_blocking(move || store.opportunities(&filter))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Venue call failures per class (transport, rate_limited, revert, no_pool, ...) since startup
+pub async fn get_error_counts(stats: Arc<ErrorStats>) -> Json<BTreeMap<&'static str, ClassCount>> {
+    Json(stats.snapshot())
+}
+
 pub async fn dashboard() -> Html<&'static str> {
     Html(r#"
 <!DOCTYPE html>
@@ -332,6 +419,64 @@ pub async fn dashboard() -> Html<&'static str> {
             font-size: 1.1rem;
         }
 
+        /* Per-pair breakdown */
+        .pair-breakdown {
+            margin-top: 24px;
+            display: flex;
+            flex-direction: column;
+            gap: 8px;
+        }
+
+        .pair-row {
+            display: flex;
+            justify-content: space-between;
+            align-items: center;
+            padding: 10px 16px;
+            border-radius: 10px;
+            background: rgba(102, 126, 234, 0.08);
+            font-size: 0.95rem;
+        }
+
+        .pair-name {
+            font-weight: 600;
+        }
+
+        .pair-stats {
+            color: var(--text-secondary);
+        }
+
+        /* Spread histogram */
+        .histogram {
+            display: flex;
+            align-items: flex-end;
+            gap: 6px;
+            width: 100%;
+            height: 100%;
+            padding: 16px;
+        }
+
+        .histogram-bar {
+            flex: 1;
+            height: 100%;
+            display: flex;
+            flex-direction: column;
+            justify-content: flex-end;
+            align-items: center;
+            gap: 4px;
+            font-size: 0.7rem;
+        }
+
+        .histogram-fill {
+            width: 100%;
+            min-height: 2px;
+            border-radius: 6px 6px 0 0;
+            background: var(--primary-gradient);
+        }
+
+        .histogram-fill.positive {
+            background: var(--success-gradient);
+        }
+
         /* Arbitrage List */
         .arbitrage-container {
             background: var(--card-bg);
@@ -613,7 +758,7 @@ pub async fn dashboard() -> Html<&'static str> {
                     <div class="stat-icon">💰</div>
                 </div>
                 <div class="stat-value" id="total-profit">0.00</div>
-                <div class="stat-title">Total Profit (USDC)</div>
+                <div class="stat-title">Total Profit (quote)</div>
                 <div class="stat-change positive">
                     <span>↗</span>
                     <span id="profit-change">+0%</span>
@@ -648,12 +793,15 @@ pub async fn dashboard() -> Html<&'static str> {
         <div class="content-grid">
             <div class="chart-container">
                 <div class="chart-header">
-                    <h3 class="chart-title">📊 Profit Trend Analysis</h3>
-                    <div class="arbitrage-count" id="chart-timeframe">Last 24h</div>
+                    <h3 class="chart-title">📊 Spread Distribution</h3>
+                    <div class="arbitrage-count" id="chart-timeframe">No evaluations yet</div>
                 </div>
                 <div class="chart-canvas" id="profit-chart">
-                    <div>Real-time chart will be rendered here</div>
+                    <div>Waiting for evaluations...</div>
                 </div>
+                <div class="pair-breakdown" id="spread-summary"></div>
+                <div class="pair-breakdown" id="pair-breakdown"></div>
+                <div class="pair-breakdown" id="error-breakdown"></div>
             </div>
 
             <div class="arbitrage-container">
@@ -708,6 +856,8 @@ pub async fn dashboard() -> Html<&'static str> {
                     
                     this.updateUI();
                     this.updateStatus('Connected', 'connected');
+                    await this.fetchErrors();
+                    await this.fetchSpreads();
                 } catch (error) {
                     console.error('Failed to fetch data:', error);
                     this.retryCount++;
@@ -721,21 +871,84 @@ pub async fn dashboard() -> Html<&'static str> {
                 }
             }
 
+            async fetchErrors() {
+                try {
+                    const response = await fetch('/api/errors');
+                    if (!response.ok) throw new Error(`HTTP ${response.status}`);
+                    this.updateErrorBreakdown(await response.json());
+                } catch (error) {
+                    console.error('Failed to fetch error counts:', error);
+                }
+            }
+
+            async fetchSpreads() {
+                try {
+                    const response = await fetch('/api/spreads');
+                    if (!response.ok) throw new Error(`HTTP ${response.status}`);
+                    this.updateSpreadHistogram(await response.json());
+                } catch (error) {
+                    console.error('Failed to fetch spreads:', error);
+                }
+            }
+
+            updateSpreadHistogram(stats) {
+                if (stats.evaluations === 0) return;
+                document.getElementById('chart-timeframe').textContent = `Last ${stats.evaluations.toLocaleString()} evaluations`;
+
+                const max = Math.max(1, ...stats.histogram.map(([, count]) => count));
+                document.getElementById('profit-chart').innerHTML = `
+                    <div class="histogram">
+                        ${stats.histogram.map(([label, count], i) => `
+                            <div class="histogram-bar" title="${count} evaluations">
+                                <span>${count}</span>
+                                <div class="histogram-fill ${i >= stats.histogram.length / 2 ? 'positive' : ''}" style="height: ${count / max * 80}%"></div>
+                                <span>${this.escapeHtml(label)}</span>
+                            </div>
+                        `).join('')}
+                    </div>
+                `;
+
+                const pct = (n) => (n / stats.evaluations * 100).toFixed(1) + '%';
+                const closest = stats.closest_to_threshold != null ? `${(stats.closest_to_threshold * 100).toFixed(0)}% of threshold` : '—';
+                document.getElementById('spread-summary').innerHTML = `
+                    <div class="pair-row">
+                        <span class="pair-name">Positive spread</span>
+                        <span class="pair-stats">${stats.positive_spread} (${pct(stats.positive_spread)}) · ${stats.profitable} profitable · ${stats.failed} failed</span>
+                    </div>
+                    <div class="pair-row">
+                        <span class="pair-name">Near misses</span>
+                        <span class="pair-stats">${stats.near_misses} · closest ${closest}</span>
+                    </div>
+                `;
+            }
+
+            updateErrorBreakdown(counts) {
+                // "no_pool" means the venue lacks the pair; "transport"/"rate_limited"/"timeout" mean the RPC is struggling
+                document.getElementById('error-breakdown').innerHTML = Object.entries(counts)
+                    .map(([kind, count]) => `
+                        <div class="pair-row">
+                            <span class="pair-name">⚠ ${this.escapeHtml(kind.replace('_', ' '))}</span>
+                            <span class="pair-stats">${count.failures} failed · ${count.retries} retries · ${count.recovered} recovered</span>
+                        </div>
+                    `).join('');
+            }
+
             updateUI() {
                 this.updateStats();
+                this.updatePairBreakdown();
                 this.updateArbitrageList();
                 this.updateLastUpdateTime();
             }
 
             updateStats() {
                 const totalOpportunities = this.data.length;
-                const totalProfit = this.data.reduce((sum, item) => sum + item.profit_usdc, 0);
+                const totalProfit = this.data.reduce((sum, item) => sum + (item.optimal_profit ?? item.net_profit), 0);
                 const avgProfit = totalOpportunities > 0 ? totalProfit / totalOpportunities : 0;
                 const successRate = totalOpportunities > 0 ? 100 : 0; // Simplified success rate
 
                 // Calculate changes from previous data
                 const prevTotal = this.previousData.length;
-                const prevTotalProfit = this.previousData.reduce((sum, item) => sum + item.profit_usdc, 0);
+                const prevTotalProfit = this.previousData.reduce((sum, item) => sum + (item.optimal_profit ?? item.net_profit), 0);
                 
                 const opportunitiesChange = prevTotal > 0 ? ((totalOpportunities - prevTotal) / prevTotal * 100).toFixed(1) : 0;
                 const profitChange = prevTotalProfit > 0 ? ((totalProfit - prevTotalProfit) / prevTotalProfit * 100).toFixed(1) : 0;
@@ -752,6 +965,24 @@ pub async fn dashboard() -> Html<&'static str> {
                 document.getElementById('rate-change').textContent = '+0%'; // Placeholder
             }
 
+            updatePairBreakdown() {
+                const byPair = {};
+                this.data.forEach(item => {
+                    const entry = byPair[item.pair] || (byPair[item.pair] = { count: 0, profit: 0 });
+                    entry.count++;
+                    entry.profit += (item.optimal_profit ?? item.net_profit);
+                });
+
+                document.getElementById('pair-breakdown').innerHTML = Object.entries(byPair)
+                    .sort((a, b) => b[1].profit - a[1].profit)
+                    .map(([pair, entry]) => `
+                        <div class="pair-row">
+                            <span class="pair-name">${this.escapeHtml(pair)}</span>
+                            <span class="pair-stats">${entry.count} opportunities · ${entry.profit.toFixed(2)} profit</span>
+                        </div>
+                    `).join('');
+            }
+
             updateArbitrageList() {
                 const listContainer = document.getElementById('arbitrage-list');
                 const liveCount = document.getElementById('live-count');
@@ -781,26 +1012,27 @@ pub async fn dashboard() -> Html<&'static str> {
 
                 // Sort by profit (descending) and take top 10
                 const recentData = [...this.data]
-                    .sort((a, b) => b.profit_usdc - a.profit_usdc)
+                    .sort((a, b) => (b.optimal_profit ?? b.net_profit) - (a.optimal_profit ?? a.net_profit))
                     .slice(0, 10);
 
                 listContainer.innerHTML = recentData.map((item, index) => `
                     <div class="arb-item" style="animation-delay: ${index * 0.1}s">
                         <div class="arb-header">
-                            <div class="arb-direction">${this.escapeHtml(item.direction)}</div>
+                            <div class="arb-direction">${this.escapeHtml(item.pair)} · ${this.escapeHtml(item.direction)}</div>
                             <div class="arb-profit">
-                                <span>+$${item.profit_usdc.toFixed(2)}</span>
-                                <span class="arb-badge">USDC</span>
+                                <span>+${(item.optimal_profit ?? item.net_profit).toFixed(2)}</span>
+                                <span class="arb-badge">${this.escapeHtml(item.quote_symbol)}</span>
                             </div>
                         </div>
                         <div class="arb-details">
                             <div class="arb-timestamp">
                                 <span>🕐</span>
-                                <span>${new Date(item.timestamp * 1000).toLocaleString()}</span>
+                                <span>${new Date(item.timestamp * 1000).toLocaleString()} · #${item.block_number}</span>
                             </div>
+                            <div class="arb-amount">${item.amount_in.toFixed(2)} ${this.escapeHtml(item.quote_symbol)} → ${item.intermediate_amount.toFixed(6)} ${this.escapeHtml(item.path[1])}${item.optimal_amount != null ? ` · optimal ${item.optimal_amount.toFixed(2)} → +${item.optimal_profit.toFixed(2)}` : ''}</div>
                             <div class="arb-confidence">
-                                <span style="color: var(--success-color);">●</span>
-                                <span>High Confidence</span>
+                                <span style="color: var(${item.robust ? '--success-color' : '--warning-color'});">●</span>
+                                <span>${item.robust ? 'Robust' : 'Fragile'} · impact ${item.price_impact.toFixed(3)}% · ${item.slippage_profit.toFixed(2)} at slippage</span>
                             </div>
                         </div>
                     </div>
@@ -880,13 +1112,21 @@ pub async fn dashboard() -> Html<&'static str> {
     "#)
 }
 
-pub async fn start_server(state: SharedArbData) {
+pub async fn start_server(
+    state: SharedOpportunities,
+    evaluations: SharedEvaluations,
+    errors: Arc<ErrorStats>,
+    store: Option<Arc<Store>>,
+) {
     let app = Router::new()
         .route("/", get(dashboard))
         .route("/api/arb", get({
             let state = state.clone();
             move || get_arb_data(state.clone())
-        }));
+        }))
+        .route("/api/spreads", get(move || get_spread_stats(evaluations.clone())))
+        .route("/api/errors", get(move || get_error_counts(errors.clone())))
+        .route("/api/opportunities", get(move |Query(filter): Query<Filter>| query_opportunities(store.clone(), filter)));
 
     println!("Web monitoring server starting on http://localhost:3000");
     